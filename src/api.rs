@@ -1,150 +1,789 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::sync::RwLock;
+use std::time::Duration;
 use anyhow::anyhow;
-use reqwest::{Client, ClientBuilder};
+use reqwest::header::{HeaderValue, AUTHORIZATION};
+use reqwest::{Client, ClientBuilder, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::archive::ItemKind;
+use crate::backoff::{retry_after, Backoff};
 use crate::comment::Comment;
+use crate::community::Community;
+use crate::person::Person;
 use crate::configuration::Configuration;
+use crate::helper::parse_timestamp;
 use crate::post::Post;
+use crate::redact::redact;
+use crate::shutdown::Shutdown;
+use crate::v4;
+use crate::version::ApiVersion;
+
+/// The errors Lemmy reports in `{"error": "..."}` bodies that this tool can act on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum LemmyError {
+    RateLimit,
+    NotLoggedIn,
+    IncorrectLogin,
+    CouldntUpdateComment,
+    CouldntUpdatePost,
+    NoCommentEditAllowed,
+    NoPostEditAllowed,
+    CouldntFindComment,
+    CouldntFindPost,
+    /// Any other error, with the code as reported by Lemmy.
+    Other(String),
+}
+
+impl From<&str> for LemmyError {
+    fn from(code: &str) -> Self {
+        match code {
+            "rate_limit_error" => LemmyError::RateLimit,
+            "not_logged_in" => LemmyError::NotLoggedIn,
+            "incorrect_login" => LemmyError::IncorrectLogin,
+            "couldnt_update_comment" => LemmyError::CouldntUpdateComment,
+            "couldnt_update_post" => LemmyError::CouldntUpdatePost,
+            "no_comment_edit_allowed" => LemmyError::NoCommentEditAllowed,
+            "no_post_edit_allowed" => LemmyError::NoPostEditAllowed,
+            "couldnt_find_comment" => LemmyError::CouldntFindComment,
+            "couldnt_find_post" => LemmyError::CouldntFindPost,
+            other => LemmyError::Other(other.to_string()),
+        }
+    }
+}
+
+impl Display for LemmyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let code = match self {
+            LemmyError::RateLimit => "rate_limit_error",
+            LemmyError::NotLoggedIn => "not_logged_in",
+            LemmyError::IncorrectLogin => "incorrect_login",
+            LemmyError::CouldntUpdateComment => "couldnt_update_comment",
+            LemmyError::CouldntUpdatePost => "couldnt_update_post",
+            LemmyError::NoCommentEditAllowed => "no_comment_edit_allowed",
+            LemmyError::NoPostEditAllowed => "no_post_edit_allowed",
+            LemmyError::CouldntFindComment => "couldnt_find_comment",
+            LemmyError::CouldntFindPost => "couldnt_find_post",
+            LemmyError::Other(code) => code,
+        };
+
+        write!(f, "{code}")
+    }
+}
+
+/// The body Lemmy sends along with an error status.
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: String,
+}
+
+/// What to do after a failed API call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Action {
+    /// The problem is temporary, try again after waiting.
+    Retry,
+    /// The problem is specific to this item, move on to the next one.
+    Skip,
+    /// Further calls will fail as well, stop the run.
+    Abort,
+}
+
+/// An error returned by an API call.
+#[derive(Debug)]
+pub(crate) enum ApiError {
+    /// The server answered with an error in Lemmy's format.
+    Lemmy {
+        status: StatusCode,
+        error: LemmyError,
+        /// How long the server asked us to wait before trying again, i.e. when rate limited.
+        retry_after: Option<Duration>,
+    },
+    /// The server answered with an error status and no Lemmy error, i.e. from a reverse proxy.
+    Http {
+        status: StatusCode,
+        body: String,
+        /// How long the server asked us to wait before trying again.
+        retry_after: Option<Duration>,
+    },
+    /// The server could not be reached, or the connection broke. The URL is left out of the error,
+    /// as servers that don't take the token as a header get it in the query string.
+    Network(reqwest::Error),
+    /// The server claimed success, but the response could not be parsed.
+    InvalidResponse(serde_json::Error, String),
+    /// Logging in again after the token was rejected failed.
+    Login(anyhow::Error),
+    /// The process was asked to stop while waiting to try again.
+    Interrupted(Box<ApiError>),
+}
+
+impl ApiError {
+    /// Turn an error response into an error, reading Lemmy's error code if there is one.
+    pub async fn from_response(response: Response) -> Self {
+        let status = response.status();
+        let retry_after = retry_after(&response);
+        let body = match response.text().await {
+            Ok(body) => body,
+            Err(error) => return ApiError::network(error),
+        };
+
+        match serde_json::from_str::<ErrorBody>(&body) {
+            Ok(error) => ApiError::Lemmy { status, error: LemmyError::from(&error.error[..]), retry_after },
+            Err(_) => ApiError::Http { status, body, retry_after },
+        }
+    }
+
+    fn network(error: reqwest::Error) -> Self {
+        ApiError::Network(error.without_url())
+    }
+
+    /// Whether the server did not accept the token.
+    pub fn is_auth_failure(&self) -> bool {
+        match self {
+            ApiError::Lemmy { error: LemmyError::NotLoggedIn | LemmyError::IncorrectLogin, .. } => true,
+            ApiError::Lemmy { status, .. } | ApiError::Http { status, .. } => *status == StatusCode::UNAUTHORIZED,
+            ApiError::Login(_) => true,
+            _ => false,
+        }
+    }
+
+    /// How long the server asked us to wait before trying again, if it said so.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ApiError::Lemmy { retry_after, .. } | ApiError::Http { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// Decide what to do about this error.
+    pub fn action(&self) -> Action {
+        if self.is_auth_failure() {
+            return Action::Abort;
+        }
+
+        match self {
+            ApiError::Lemmy { error: LemmyError::RateLimit, .. } => Action::Retry,
+            ApiError::Lemmy { .. } => Action::Skip,
+            ApiError::Http { status, .. } => match status.as_u16() {
+                // Rate limited by Lemmy or a reverse proxy
+                429 => Action::Retry,
+                // Overwhelmed server or a reverse proxy that can't reach it
+                502..=504 => Action::Retry,
+                _ => Action::Skip,
+            },
+            ApiError::Network(error) if error.is_timeout() || error.is_connect() => Action::Retry,
+            ApiError::Network(_) => Action::Skip,
+            ApiError::InvalidResponse(..) => Action::Skip,
+            ApiError::Login(_) => Action::Abort,
+            ApiError::Interrupted(_) => Action::Abort,
+        }
+    }
+}
+
+/// Check whether an error means the rest of the run should be abandoned, instead of moving on to
+/// the next item.
+pub(crate) fn is_fatal(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<ApiError>().is_some_and(|error| error.action() == Action::Abort)
+}
+
+/// Check whether an error means a request was given up on because a shutdown was requested. That
+/// is not a failure of the item: it is left for the next run, like the items that were never tried.
+pub(crate) fn is_interrupted(error: &anyhow::Error) -> bool {
+    matches!(error.downcast_ref::<ApiError>(), Some(ApiError::Interrupted(_)))
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // Response bodies are written by the server or a proxy, and may echo the request
+        let message = match self {
+            ApiError::Lemmy { status, error, .. } => format!("Lemmy error {error} ({status})"),
+            ApiError::Http { status, body, .. } => format!("HTTP error ({status}): {body}"),
+            ApiError::Network(error) => format!("Network error: {error}"),
+            ApiError::InvalidResponse(error, body) => format!("Invalid response ({error}): {body}"),
+            ApiError::Login(error) => format!("Logging in again failed: {error:#}"),
+            ApiError::Interrupted(error) => format!("Stopped instead of retrying after: {error}"),
+        };
+
+        write!(f, "{}", redact(&message))
+    }
+}
+
+impl Error for ApiError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ApiError::Network(error) => Some(error),
+            ApiError::InvalidResponse(error, _) => Some(error),
+            _ => None,
+        }
+    }
+}
 
 /// An API client for Lemmy. Quite barebones.
+///
+/// The endpoints used by this tool are wrapped in methods that hide the differences between the
+/// API versions, and return the shapes of the v3 API.
+///
+/// A single instance is created for every account and used for the whole run (or for every run
+/// of the daemon), so that connections to the server are reused and a token from a login is kept.
+/// The version and the token are still checked again at the start of every run.
 pub(crate) struct Api {
     base_url: String,
     pub(crate) client: Client,
+    /// The token used for API calls. It may come from a login, and can be replaced halfway through
+    /// a run if it expires.
+    token: RwLock<Option<String>>,
+    /// The API version of the server, which decides the endpoints and the way to send the token.
+    version: RwLock<Option<ApiVersion>>,
+    /// The ID of the configured user on the home instance, known once the token has been verified.
+    person_id: RwLock<Option<i64>>,
+    /// Cuts waiting for a retry short when the process is asked to stop.
+    shutdown: Shutdown,
 }
 
 impl Api {
+    /// Create a client for the instance of the configured user.
+    pub fn new(config: &Configuration, shutdown: &Shutdown) -> anyhow::Result<Self> {
+        let domain = config.instance().ok_or(anyhow!("Invalid username"))?;
+
+        let client = ClientBuilder::new()
+            .user_agent("LemmyAutoDeleteBot/0.1.0")
+            .build()?;
+
+        Ok(Self {
+            base_url: format!("https://{domain}"),
+            client,
+            shutdown: shutdown.clone(),
+            token: RwLock::new(None),
+            version: RwLock::new(None),
+            person_id: RwLock::new(None),
+        })
+    }
+
+    pub fn set_token(&self, token: String) {
+        crate::redact::register(&token);
+        *self.token.write().unwrap() = Some(token);
+    }
+
+    /// The token to authenticate API calls with.
+    pub fn token(&self) -> String {
+        self.token.read().unwrap().clone().unwrap_or_default()
+    }
+
+    pub fn has_token(&self) -> bool {
+        self.token.read().unwrap().is_some()
+    }
+
+    pub fn set_version(&self, version: ApiVersion) {
+        *self.version.write().unwrap() = Some(version);
+    }
+
+    /// The API version of the server. Until it has been detected, the newest supported version is assumed.
+    pub fn version(&self) -> ApiVersion {
+        self.version.read().unwrap().unwrap_or(ApiVersion::V0_19)
+    }
+
+    pub fn set_person_id(&self, person_id: i64) {
+        *self.person_id.write().unwrap() = Some(person_id);
+    }
+
+    fn person_id(&self) -> i64 {
+        self.person_id.read().unwrap().unwrap_or_default()
+    }
+
+    /// The token to put in request bodies, for servers that expect it there.
+    fn body_auth(&self) -> Option<String> {
+        (!self.version().uses_auth_header()).then(|| self.token())
+    }
+
     /// Generate the URL for a Lemmy API endpoint.
     pub fn format_api_call(&self, path: &str) -> String {
-        format!("{}/api/v3/{path}", self.base_url)
+        format!("{}/{}/{path}", self.base_url, self.version().prefix())
     }
 
-    /// Build a reqwest client. Used for initialisation.
-    fn build_client() -> Client {
-        ClientBuilder::new()
-            .user_agent("LemmyAutoDeleteBot/0.1.0")
-            .build()
-            .unwrap()
+    /// The URL to ask for the version of the server. This is part of the v3 API, which every
+    /// supported version serves, whatever version has been detected before.
+    pub fn version_url(&self) -> String {
+        format!("{}/api/v3/site", self.base_url)
     }
-}
 
-impl Default for Api {
-    fn default() -> Self {
-        Self {
-            base_url: String::new(),
-            client: Self::build_client(),
+    /// The endpoint to log in with.
+    pub fn login_path(&self) -> &'static str {
+        match self.version() {
+            ApiVersion::V0_18 | ApiVersion::V0_19 => "user/login",
+            ApiVersion::V4 => "account/auth/login",
         }
     }
-}
 
-impl TryFrom<&Configuration> for Api {
-    type Error = anyhow::Error;
+    /// Fetch the account the token belongs to. Returns `None` if the server did not recognise it.
+    pub async fn my_person(&self, config: &Configuration) -> Result<Option<Person>, ApiError> {
+        match self.version() {
+            ApiVersion::V0_18 | ApiVersion::V0_19 => {
+                let response: SiteResponse = self.get(config, "site").await?;
+                Ok(response.my_user.map(|user| user.local_user_view.person))
+            }
+            ApiVersion::V4 => {
+                let response: MyUserInfo = self.get(config, "account").await?;
+                Ok(Some(response.local_user_view.person))
+            }
+        }
+    }
 
-    fn try_from(value: &Configuration) -> anyhow::Result<Self> {
-        let (_user, domain) = value.canonical_username()
-            .split_once('@')
-            .ok_or(anyhow!("Invalid username"))?;
+    /// Fetch a page of the posts and comments of the configured user.
+    ///
+    /// Servers with numbered pages are asked for the given page, oldest items first. Other servers
+    /// are asked for the page after the cursor of the previous page, newest items first.
+    pub async fn profile_page(&self, config: &Configuration, page: i64, cursor: Option<&str>) -> Result<ProfilePage, ApiError> {
+        match self.version() {
+            ApiVersion::V0_18 | ApiVersion::V0_19 => {
+                self.get(config, &format!("user?username={}&sort=Old&page={page}&limit=50", config.canonical_username())).await
+            }
+            ApiVersion::V4 => {
+                let mut path = format!("person/content?person_id={}&limit=50", self.person_id());
+                if let Some(cursor) = cursor {
+                    path.push_str("&page_cursor=");
+                    path.extend(url::form_urlencoded::byte_serialize(cursor.as_bytes()));
+                }
+                let response: v4::PersonContentResponse = self.get(config, &path).await?;
+                Ok(response.into())
+            }
+        }
+    }
 
-        Ok(Self {
-            base_url: format!("https://{domain}"),
-            client: Self::build_client(),
-        })
+    /// Fetch a comment by its ID on the home instance.
+    pub async fn comment(&self, config: &Configuration, comment_id: i64) -> Result<CommentView, ApiError> {
+        let path = format!("comment?id={comment_id}");
+        match self.version() {
+            ApiVersion::V0_18 | ApiVersion::V0_19 => self.get::<CommentResponse>(config, &path).await.map(|response| response.comment_view),
+            ApiVersion::V4 => self.get::<v4::CommentResponse>(config, &path).await.map(|response| response.comment_view.into()),
+        }
+    }
+
+    /// Fetch a post by its ID on the home instance.
+    pub async fn post_by_id(&self, config: &Configuration, post_id: i64) -> Result<PostView, ApiError> {
+        let path = format!("post?id={post_id}");
+        match self.version() {
+            ApiVersion::V0_18 | ApiVersion::V0_19 => self.get::<PostResponse>(config, &path).await.map(|response| response.post_view),
+            ApiVersion::V4 => self.get::<v4::PostResponse>(config, &path).await.map(|response| response.post_view.into()),
+        }
+    }
+
+    /// Look up a post or comment by its URL, through the home instance. Returns `None` if the URL
+    /// is something else, like a community.
+    pub async fn resolve(&self, config: &Configuration, url: &str) -> Result<Option<ContentView>, ApiError> {
+        let query: String = url::form_urlencoded::byte_serialize(url.as_bytes()).collect();
+        let path = format!("resolve_object?q={query}");
+        match self.version() {
+            ApiVersion::V0_18 | ApiVersion::V0_19 => {
+                let response: ResolveObjectResponse = self.get(config, &path).await?;
+                Ok(match (response.comment, response.post) {
+                    (Some(comment), _) => Some(ContentView::Comment(comment)),
+                    (None, Some(post)) => Some(ContentView::Post(post)),
+                    (None, None) => None,
+                })
+            }
+            ApiVersion::V4 => {
+                let response: v4::ResolveObjectResponse = self.get(config, &path).await?;
+                Ok(match response.resolve {
+                    Some(v4::ContentView::Comment(comment)) => Some(ContentView::Comment(comment.into())),
+                    Some(v4::ContentView::Post(post)) => Some(ContentView::Post(post.into())),
+                    Some(v4::ContentView::Other) | None => None,
+                })
+            }
+        }
+    }
+
+    /// Delete or undelete a post.
+    pub async fn delete_post(&self, config: &Configuration, post_id: i64, deleted: bool) -> Result<PostView, ApiError> {
+        let body = || PostIdBody { auth: self.body_auth(), post_id, deleted };
+        match self.version() {
+            ApiVersion::V0_18 | ApiVersion::V0_19 => self.post::<_, PostResponse>(config, "post/delete", body).await.map(|response| response.post_view),
+            ApiVersion::V4 => self.post::<_, v4::PostResponse>(config, "post/delete", body).await.map(|response| response.post_view.into()),
+        }
+    }
+
+    /// Delete or undelete a comment.
+    pub async fn delete_comment(&self, config: &Configuration, comment_id: i64, deleted: bool) -> Result<CommentView, ApiError> {
+        let body = || DeleteCommentBody { auth: self.body_auth(), comment_id, deleted };
+        match self.version() {
+            ApiVersion::V0_18 | ApiVersion::V0_19 => self.post::<_, CommentResponse>(config, "comment/delete", body).await.map(|response| response.comment_view),
+            ApiVersion::V4 => self.post::<_, v4::CommentResponse>(config, "comment/delete", body).await.map(|response| response.comment_view.into()),
+        }
+    }
+
+    /// Replace the contents of a comment.
+    pub async fn edit_comment(&self, config: &Configuration, comment_id: i64, content: &str) -> Result<CommentView, ApiError> {
+        let body = || EditCommentBody { auth: self.body_auth(), comment_id, content: content.to_string() };
+        match self.version() {
+            ApiVersion::V0_18 | ApiVersion::V0_19 => self.put::<_, CommentResponse>(config, "comment", body).await.map(|response| response.comment_view),
+            ApiVersion::V4 => self.put::<_, v4::CommentResponse>(config, "comment", body).await.map(|response| response.comment_view.into()),
+        }
+    }
+
+    /// Send a GET request and parse the response.
+    ///
+    /// Servers that don't take the token as a header get it as the `auth` query parameter.
+    async fn get<R: DeserializeOwned>(&self, config: &Configuration, path: &str) -> Result<R, ApiError> {
+        self.request(config, || {
+            let request = self.client.get(self.format_api_call(path));
+            if self.version().uses_auth_header() {
+                request.header(AUTHORIZATION, self.auth_header())
+            } else {
+                request.query(&[("auth", self.token())])
+            }
+        }).await
+    }
+
+    /// Send a POST request with a JSON body and parse the response.
+    ///
+    /// Servers that don't take the token as a header expect the body to carry it.
+    async fn post<B: Serialize, R: DeserializeOwned>(&self, config: &Configuration, path: &str, body: impl Fn() -> B) -> Result<R, ApiError> {
+        self.request(config, || self.authorize(self.client
+            .post(self.format_api_call(path))
+            .json(&body())))
+            .await
+    }
+
+    /// Send a PUT request with a JSON body and parse the response.
+    ///
+    /// Servers that don't take the token as a header expect the body to carry it.
+    async fn put<B: Serialize, R: DeserializeOwned>(&self, config: &Configuration, path: &str, body: impl Fn() -> B) -> Result<R, ApiError> {
+        self.request(config, || self.authorize(self.client
+            .put(self.format_api_call(path))
+            .json(&body())))
+            .await
+    }
+
+    /// Add the Authorization header to a request, if the server takes the token that way.
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        if self.version().uses_auth_header() {
+            request.header(AUTHORIZATION, self.auth_header())
+        } else {
+            request
+        }
+    }
+
+    /// The Authorization header carrying the token, flagged so that reqwest never prints it.
+    fn auth_header(&self) -> HeaderValue {
+        let mut value = HeaderValue::from_str(&format!("Bearer {}", self.token()))
+            .unwrap_or(HeaderValue::from_static(""));
+        value.set_sensitive(true);
+        value
+    }
+
+    /// Send a request built by `build` and parse the response, retrying when the error allows it.
+    ///
+    /// The request is rebuilt for every attempt, so that it carries the current token. If the server
+    /// rejects the token and a password has been configured, this logs in again once. Retrying stops
+    /// as soon as the process is asked to stop.
+    async fn request<R: DeserializeOwned>(&self, config: &Configuration, build: impl Fn() -> RequestBuilder) -> Result<R, ApiError> {
+        let backoff = Backoff::new(config);
+        let mut attempt = 1;
+        let mut logged_in_again = false;
+
+        loop {
+            let error = match Self::request_once(build()).await {
+                Ok(response) => return Ok(response),
+                Err(error) => error,
+            };
+
+            if error.is_auth_failure() && !logged_in_again && config.can_login() {
+                eprintln!("The server rejected the token, logging in again");
+                crate::auth::login(config, self).await.map_err(ApiError::Login)?;
+                logged_in_again = true;
+                continue;
+            }
+
+            if error.action() == Action::Retry {
+                if let Some(delay) = backoff.delay(attempt, error.retry_after()) {
+                    if self.shutdown.requested() {
+                        return Err(ApiError::Interrupted(Box::new(error)));
+                    }
+
+                    eprintln!("{error}, retrying in {:.1}s (attempt {attempt}/{})", delay.as_secs_f64(), backoff.max_attempts());
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => {}
+                        _ = self.shutdown.wait() => return Err(ApiError::Interrupted(Box::new(error))),
+                    }
+                    attempt += 1;
+                    continue;
+                }
+            }
+
+            return Err(error);
+        }
+    }
+
+    /// Send a request once and parse the response.
+    async fn request_once<R: DeserializeOwned>(request: RequestBuilder) -> Result<R, ApiError> {
+        let response = request.send().await.map_err(ApiError::network)?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        let body = response.text().await.map_err(ApiError::network)?;
+        serde_json::from_str(&body).map_err(|error| ApiError::InvalidResponse(error, body))
     }
 }
 
 /// A struct representing comments on a profile. Simplified.
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub(crate) struct CommentView {
     /// The comment details itself.
     pub(crate) comment: Comment,
+    /// The author of the comment.
+    pub(crate) creator: Person,
+    /// The community the comment was placed in.
+    pub(crate) community: Community,
+    /// Vote and reply counts for the comment.
+    pub(crate) counts: CommentAggregates,
     /// Whether the comment has been saved by the user or not.
     pub(crate) saved: bool,
     /// What vote the user gave to this comment (1, 0, -1)
     pub(crate) my_vote: Option<i64>,
 }
 
-/// A struct representing the response to an edit API call. Simplified.
+/// The aggregated counts Lemmy keeps for a comment. Simplified.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct CommentAggregates {
+    /// Upvotes minus downvotes.
+    pub(crate) score: i64,
+    /// The amount of replies to the comment, including replies to replies.
+    pub(crate) child_count: i64,
+}
+
+/// The aggregated counts Lemmy keeps for a post. Simplified.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct PostAggregates {
+    /// Upvotes minus downvotes.
+    pub(crate) score: i64,
+    /// The amount of comments on the post.
+    pub(crate) comments: i64,
+}
+
+/// A struct representing the response to an API call that returns a comment. Simplified.
 #[derive(Deserialize)]
-pub(crate) struct CommentEditResponse {
-    pub(crate) comment_view: CommentView,
+struct CommentResponse {
+    comment_view: CommentView,
 }
 
-/// A struct representing the response to a post delete API call. Simplified.
+/// A struct representing the response to an API call that returns a post. Simplified.
 #[derive(Deserialize)]
-pub(crate) struct PostDeleteResponse {
-    pub(crate) post_view: PostView,
+struct PostResponse {
+    post_view: PostView,
 }
 
-/// A struct representing the posts on a profile. Simplified.
+/// A struct representing the response to a resolve object API call, which looks up a federated ID
+/// on the home instance. Simplified.
 #[derive(Deserialize)]
+struct ResolveObjectResponse {
+    comment: Option<CommentView>,
+    post: Option<PostView>,
+}
+
+/// A post or a comment, for API calls that can return either.
+pub(crate) enum ContentView {
+    Comment(CommentView),
+    Post(PostView),
+}
+
+/// A struct representing the posts on a profile. Simplified.
+#[derive(Deserialize, Serialize)]
 pub(crate) struct PostView {
     pub(crate) post: Post,
+    /// The author of the post.
+    pub(crate) creator: Person,
+    /// The community the post was placed in.
+    pub(crate) community: Community,
+    /// Vote and comment counts for the post.
+    pub(crate) counts: PostAggregates,
     pub(crate) saved: bool,
     pub(crate) my_vote: Option<i64>,
     pub(crate) deleted: Option<bool>,
 }
 
+/// A struct representing the response to a site API call. Simplified.
+#[derive(Deserialize)]
+struct SiteResponse {
+    /// The account the token belongs to. Missing if the call was not authenticated.
+    my_user: Option<MyUserInfo>,
+}
+
+/// A struct representing the logged in user. Simplified.
+#[derive(Deserialize)]
+struct MyUserInfo {
+    local_user_view: LocalUserView,
+}
+
+/// A struct representing a local user account. Simplified.
+#[derive(Deserialize)]
+struct LocalUserView {
+    person: Person,
+}
+
 /// A struct representing a single page of a profile. Simplified.
 #[derive(Deserialize)]
+#[serde(from = "RawProfilePage")]
 pub(crate) struct ProfilePage {
     /// The comments for this page. This list is not complete, there may be more pages.
     pub(crate) comments: Vec<CommentView>,
     /// The posts for this page. This list is not complete, there may be more pages.
     pub(crate) posts: Vec<PostView>,
+    /// The items on this page that could not be read.
+    pub(crate) unreadable: Vec<Unreadable>,
+    /// The cursor for the next page, on servers that page with a cursor. Missing on the last page.
+    pub(crate) next_page: Option<String>,
+}
+
+/// A page of a profile as sent by the server, before the items have been read one by one.
+#[derive(Deserialize)]
+struct RawProfilePage {
+    comments: Vec<Value>,
+    posts: Vec<Value>,
+    #[serde(default)]
+    next_page: Option<String>,
+}
+
+impl From<RawProfilePage> for ProfilePage {
+    fn from(raw: RawProfilePage) -> Self {
+        let mut page = ProfilePage {
+            comments: vec![],
+            posts: vec![],
+            unreadable: vec![],
+            next_page: raw.next_page,
+        };
+
+        for value in raw.comments {
+            match Unreadable::read(ItemKind::Comment, value) {
+                Ok(view) => page.comments.push(view),
+                Err(unreadable) => page.unreadable.push(unreadable),
+            }
+        }
+        for value in raw.posts {
+            match Unreadable::read(ItemKind::Post, value) {
+                Ok(view) => page.posts.push(view),
+                Err(unreadable) => page.unreadable.push(unreadable),
+            }
+        }
+
+        page
+    }
+}
+
+/// A post or comment on a profile page that could not be read, i.e. because of a malformed
+/// timestamp. Such items are reported and left alone, rather than failing the whole page.
+pub(crate) struct Unreadable {
+    pub(crate) kind: ItemKind,
+    /// The ID of the item, unless even that could not be read.
+    pub(crate) id: Option<i64>,
+    pub(crate) error: String,
+}
+
+impl Unreadable {
+    /// Read a single item, or describe what is wrong with it.
+    pub fn read<T: DeserializeOwned>(kind: ItemKind, value: Value) -> Result<T, Self> {
+        T::deserialize(&value).map_err(|error| Self {
+            kind,
+            id: value[kind.as_str()]["id"].as_i64(),
+            error: match find_bad_timestamp(&value, "") {
+                Some(field) => format!("{field}: {error}"),
+                None => error.to_string(),
+            },
+        })
+    }
+}
+
+impl Display for Unreadable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.id {
+            Some(id) => write!(f, "{} {id}: {}", self.kind.as_str(), self.error),
+            None => write!(f, "{} with an unknown ID: {}", self.kind.as_str(), self.error),
+        }
+    }
+}
+
+/// Find the timestamp that makes an item unreadable, as a path like `comment.published`.
+fn find_bad_timestamp(value: &Value, path: &str) -> Option<String> {
+    let Value::Object(fields) = value else {
+        return None;
+    };
+
+    fields.iter().find_map(|(name, field)| {
+        let path = if path.is_empty() { name.clone() } else { format!("{path}.{name}") };
+        let is_timestamp = name == "published" || name == "updated" || name.ends_with("_at");
+
+        match field {
+            Value::String(text) if is_timestamp => parse_timestamp(text).is_err().then_some(path),
+            _ => find_bad_timestamp(field, &path),
+        }
+    })
 }
 
 /// A struct for making API calls that take a post ID and a delete flag.
 #[derive(Serialize)]
-pub(crate) struct PostIdBody {
-    /// The Lemmy auth token
-    pub(crate) auth: String,
+struct PostIdBody {
+    /// The Lemmy auth token, for servers that don't take it as a header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auth: Option<String>,
     /// The post ID to delete
-    pub(crate) post_id: i64,
+    post_id: i64,
     /// Whether to delete or not (should probably be true)
-    pub(crate) deleted: bool,
-}
-
-impl PostIdBody {
-    pub fn new(post_id: i64, auth: String) -> Self { Self { post_id, deleted: true, auth } }
+    deleted: bool,
 }
 
 /// A struct for building the body for a comment delete API call
 #[derive(Serialize)]
-pub(crate) struct DeleteCommentBody {
-    /// The Lemmy auth token
-    pub(crate) auth: String,
+struct DeleteCommentBody {
+    /// The Lemmy auth token, for servers that don't take it as a header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auth: Option<String>,
     /// The comment ID to delete
-    pub(crate) comment_id: i64,
+    comment_id: i64,
     /// Whether to delete or not (should probably be true)
-    pub(crate) deleted: bool,
-}
-
-
-impl DeleteCommentBody {
-    pub(crate) fn new(source: &Comment, configuration: &Configuration) -> Self {
-        Self {
-            auth: configuration.lemmy_token.clone(),
-            comment_id: source.id,
-            deleted: true,
-        }
-    }
+    deleted: bool,
 }
 
 /// A struct for building the body for a comment edit API call
 #[derive(Serialize)]
-pub(crate) struct EditCommentBody {
-    /// The Lemmy auth token
-    pub(crate) auth: String,
+struct EditCommentBody {
+    /// The Lemmy auth token, for servers that don't take it as a header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auth: Option<String>,
     /// The ID of the comment to edit
-    pub(crate) comment_id: i64,
+    comment_id: i64,
     /// The contents that should replace the current contents of the comment. Markdown.
-    pub(crate) content: String,
+    content: String,
 }
 
-impl EditCommentBody {
-    pub fn new(source: &Comment, config: &Configuration) -> Self {
-        Self {
-            auth: config.lemmy_token.clone(),
-            comment_id: source.id,
-            content: config.encoded_edit_text().to_string(),
-        }
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::archive::ItemKind;
+    use crate::fixtures::comment_json;
+    use super::{CommentView, ProfilePage, Unreadable};
+
+    #[test]
+    fn unreadable_names_the_field() {
+        let Err(unreadable) = Unreadable::read::<CommentView>(ItemKind::Comment, comment_json(12, "Hello", 1, "last tuesday")) else {
+            panic!("the comment should not be readable");
+        };
+
+        assert_eq!(unreadable.id, Some(12));
+        assert!(unreadable.error.starts_with("comment.published: "), "{}", unreadable.error);
+        assert_eq!(unreadable.to_string(), format!("comment 12: {}", unreadable.error));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn bad_items_do_not_spoil_the_page() {
+        let page: ProfilePage = serde_json::from_value(json!({
+            "comments": [comment_json(1, "Hello", 1, "2024-01-02T03:04:05Z"), comment_json(2, "Hello", 1, "soon"), comment_json(3, "Hello", 1, "2024-01-02T03:04:05.123")],
+            "posts": [],
+        })).unwrap();
+
+        let ids: Vec<_> = page.comments.iter().map(|view| view.comment.id).collect();
+        assert_eq!(ids, [1, 3]);
+        assert_eq!(page.unreadable.len(), 1);
+        assert_eq!(page.unreadable[0].id, Some(2));
+    }
+}
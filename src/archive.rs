@@ -0,0 +1,238 @@
+use std::fs::{create_dir_all, read_dir, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::api::{CommentView, PostView};
+use crate::configuration::Configuration;
+use crate::redact::redact;
+
+/// The kind of item stored in an archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ItemKind {
+    Comment,
+    Post,
+}
+
+impl ItemKind {
+    /// The name used for this kind of item in files and databases.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ItemKind::Comment => "comment",
+            ItemKind::Post => "post",
+        }
+    }
+}
+
+/// A full copy of a post or comment, as it was before it was edited or deleted.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct ArchivedItem {
+    pub(crate) kind: ItemKind,
+    pub(crate) id: i64,
+    /// The federated ID of the item.
+    pub(crate) ap_id: String,
+    /// The community in `name@instance` form.
+    pub(crate) community: String,
+    pub(crate) community_id: i64,
+    /// The title of a post. Comments don't have titles.
+    pub(crate) title: Option<String>,
+    /// The contents of a comment or the body of a post. Markdown.
+    pub(crate) content: Option<String>,
+    /// The link a post points to. Comments don't have links.
+    pub(crate) url: Option<String>,
+    pub(crate) score: i64,
+    pub(crate) published: DateTime<Utc>,
+    pub(crate) updated: Option<DateTime<Utc>>,
+    /// When the item was written to the archive.
+    pub(crate) archived_at: DateTime<Utc>,
+}
+
+impl From<&CommentView> for ArchivedItem {
+    fn from(view: &CommentView) -> Self {
+        Self {
+            kind: ItemKind::Comment,
+            id: view.comment.id,
+            ap_id: view.comment.ap_id.clone(),
+            community: view.community.qualified_name(),
+            community_id: view.community.id,
+            title: None,
+            content: Some(view.comment.content.clone()),
+            url: None,
+            score: view.counts.score,
+            published: view.comment.published,
+            updated: view.comment.updated,
+            archived_at: Utc::now(),
+        }
+    }
+}
+
+impl From<&PostView> for ArchivedItem {
+    fn from(view: &PostView) -> Self {
+        Self {
+            kind: ItemKind::Post,
+            id: view.post.id,
+            ap_id: view.post.ap_id.clone(),
+            community: view.community.qualified_name(),
+            community_id: view.community.id,
+            title: Some(view.post.name.clone()),
+            content: view.post.body.clone(),
+            url: view.post.url.clone(),
+            score: view.counts.score,
+            published: view.post.published,
+            updated: view.post.updated,
+            archived_at: Utc::now(),
+        }
+    }
+}
+
+/// A JSON Lines file holding a copy of every item touched during a single run.
+pub(crate) struct Archive {
+    path: PathBuf,
+    file: File,
+}
+
+impl Archive {
+    /// Create a new archive file for this run in the configured archive directory.
+    pub fn create(config: &Configuration) -> Result<Self> {
+        create_dir_all(&config.archive_dir)
+            .with_context(|| format!("Failed to create archive directory {}", config.archive_dir.display()))?;
+
+        let file_name = format!("{}-{}.jsonl", config.canonical_username(), Utc::now().format("%Y%m%dT%H%M%S"));
+        let path = config.archive_dir.join(file_name);
+
+        let file = File::options()
+            .create_new(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to create archive file {}", path.display()))?;
+
+        Ok(Self { path, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Find all archive files written for the configured user, oldest first.
+    pub fn find_all(config: &Configuration) -> Result<Vec<PathBuf>> {
+        let prefix = format!("{}-", config.canonical_username());
+
+        let mut paths = vec![];
+        for entry in read_dir(&config.archive_dir)
+            .with_context(|| format!("Failed to read archive directory {}", config.archive_dir.display()))? {
+            let path = entry?.path();
+            let is_archive = path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(&prefix) && name.ends_with(".jsonl"));
+
+            if is_archive {
+                paths.push(path);
+            }
+        }
+
+        // The timestamp in the file name sorts chronologically
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Read back every item stored in an archive file.
+    pub fn read(path: &Path) -> Result<Vec<ArchivedItem>> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open archive file {}", path.display()))?;
+
+        let mut items = vec![];
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let item = serde_json::from_str(&line)
+                .with_context(|| format!("Invalid archive entry on line {} of {}", index + 1, path.display()))?;
+            items.push(item);
+        }
+
+        Ok(items)
+    }
+
+    /// Write an item to the archive. The data is synced to disk before this returns, so that
+    /// a successful return means the item can safely be edited or deleted.
+    pub fn store(&mut self, item: &ArchivedItem) -> Result<()> {
+        let mut line = redact(&serde_json::to_string(item)?);
+        line.push('\n');
+
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, remove_dir_all, write};
+    use chrono::{TimeZone, Utc};
+    use clap::Parser;
+    use crate::configuration::Configuration;
+    use crate::fixtures::comment;
+    use super::{Archive, ArchivedItem, ItemKind};
+
+    /// A configuration archiving to an empty directory of its own. Every test needs its own name, as
+    /// tests run in parallel.
+    fn config(name: &str) -> Configuration {
+        let directory = std::env::temp_dir().join(format!("lemmy-old-comment-purge-{}-{name}", std::process::id()));
+        let _ = remove_dir_all(&directory);
+        create_dir_all(&directory).unwrap();
+
+        Configuration::parse_from(["lemmy-old-comment-purge", "--username", "me@lemmy.example", "--archive-dir", directory.to_str().unwrap()])
+    }
+
+    #[test]
+    fn stored_items_read_back_unchanged() {
+        let config = config("round-trip");
+        let published = Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+
+        let mut archive = Archive::create(&config).unwrap();
+        archive.store(&ArchivedItem::from(&comment(12, "Hello\nwörld", 3, published))).unwrap();
+        archive.store(&ArchivedItem::from(&comment(13, "", -1, published))).unwrap();
+
+        let items = Archive::read(archive.path()).unwrap();
+        remove_dir_all(&config.archive_dir).unwrap();
+
+        assert_eq!(items.len(), 2);
+        let item = &items[0];
+        assert_eq!((item.kind, item.id, item.ap_id.as_str()), (ItemKind::Comment, 12, "https://lemmy.example/comment/12"));
+        assert_eq!((item.community.as_str(), item.community_id), ("rust@lemmy.example", 1));
+        assert_eq!((item.content.as_deref(), item.title.as_deref(), item.url.as_deref()), (Some("Hello\nwörld"), None, None));
+        assert_eq!((item.score, item.published, item.updated), (3, published, None));
+        assert_eq!((items[1].id, items[1].content.as_deref()), (13, Some("")));
+    }
+
+    #[test]
+    fn finds_only_the_archives_of_the_user_oldest_first() {
+        let config = config("find-all");
+        for name in [
+            "me@lemmy.example-20240102T000000.jsonl",
+            "me@lemmy.example-20231231T235959.jsonl",
+            "me@lemmy.example-20240101T120000.jsonl",
+            "other@lemmy.example-20240101T000000.jsonl",
+            "someme@lemmy.example-20240101T000000.jsonl",
+            "me@lemmy.example-20240101T000000.txt",
+        ] {
+            write(config.archive_dir.join(name), "").unwrap();
+        }
+
+        let found = Archive::find_all(&config).unwrap();
+        remove_dir_all(&config.archive_dir).unwrap();
+
+        let names: Vec<_> = found.iter().map(|path| path.file_name().unwrap().to_str().unwrap()).collect();
+        assert_eq!(names, [
+            "me@lemmy.example-20231231T235959.jsonl",
+            "me@lemmy.example-20240101T120000.jsonl",
+            "me@lemmy.example-20240102T000000.jsonl",
+        ]);
+        assert!(found.iter().all(|path| path.parent() == Some(config.archive_dir.as_path())));
+    }
+}
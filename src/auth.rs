@@ -0,0 +1,171 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs::{create_dir_all, read_to_string, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::api::{Api, ApiError};
+use crate::configuration::Configuration;
+
+/// The error returned when the server does not accept our credentials.
+///
+/// This is kept apart from other errors, so that the process can exit with a dedicated exit code.
+#[derive(Debug)]
+pub(crate) struct NotAuthenticated(pub(crate) String);
+
+impl Display for NotAuthenticated {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Not authenticated: {}", self.0)
+    }
+}
+
+impl Error for NotAuthenticated {}
+
+/// The body for a login API call.
+#[derive(Serialize)]
+struct LoginBody<'a> {
+    username_or_email: &'a str,
+    password: &'a str,
+    totp_2fa_token: Option<&'a str>,
+}
+
+/// The response to a login API call. Simplified.
+#[derive(Deserialize)]
+struct LoginResponse {
+    /// The token to use for further API calls. Missing if the account still needs to be verified.
+    jwt: Option<String>,
+}
+
+/// The file a token obtained by logging in is cached in, so that not every run needs to log in.
+fn token_cache_path(config: &Configuration) -> PathBuf {
+    config.state_dir.join(format!("{}.jwt", config.canonical_username()))
+}
+
+/// Log in with the configured username and password, and cache the resulting token.
+pub(crate) async fn login(config: &Configuration, api: &Api) -> Result<()> {
+    let password = config.lemmy_password.as_deref()
+        .ok_or(anyhow!("Can't log in without a password"))?;
+    let (username, _domain) = config.canonical_username()
+        .split_once('@')
+        .ok_or(anyhow!("Invalid username"))?;
+
+    println!("Logging in as {}", config.canonical_username());
+
+    let request = api.client.post(api.format_api_call(api.login_path()))
+        .json(&LoginBody {
+            username_or_email: username,
+            password,
+            totp_2fa_token: config.totp_2fa_token.as_deref(),
+        })
+        .send()
+        .await?;
+
+    if !request.status().is_success() {
+        let error = ApiError::from_response(request).await;
+        return Err(NotAuthenticated(format!("login failed: {error}")).into());
+    }
+
+    let response: LoginResponse = request.json().await?;
+    let token = response.jwt.ok_or(NotAuthenticated("login succeeded, but the server did not hand out a token. Has the account been verified?".to_string()))?;
+
+    if let Err(error) = store_cached_token(config, &token) {
+        eprintln!("Failed to cache the login token, the next run will log in again: {error}");
+    }
+    api.set_token(token);
+
+    Ok(())
+}
+
+/// Write a token to the token cache, readable by the current user only.
+fn store_cached_token(config: &Configuration, token: &str) -> Result<()> {
+    create_dir_all(&config.state_dir)?;
+
+    let path = token_cache_path(config);
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    file.write_all(token.as_bytes())?;
+
+    Ok(())
+}
+
+/// Pick the token to start the run with. Returns whether a fresh login was needed.
+///
+/// An explicitly configured token takes precedence over a cached token from an earlier login,
+/// unless a password is configured as well: the configured token is then likely to have expired,
+/// and the cache holds the token of the last login. If neither is available, this logs in with the
+/// configured password.
+async fn pick_token(config: &Configuration, api: &Api) -> Result<bool> {
+    // Keep using the token of an earlier run in the same process, it may come from a newer login
+    if api.has_token() {
+        return Ok(false);
+    }
+
+    let cached = read_to_string(token_cache_path(config))
+        .ok()
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty());
+
+    let token = match (&config.lemmy_token, cached) {
+        (Some(_), Some(cached)) if config.can_login() => Some(cached),
+        (Some(configured), _) => Some(configured.clone()),
+        (None, cached) => cached,
+    };
+    if let Some(token) = token {
+        api.set_token(token);
+        return Ok(false);
+    }
+
+    if !config.can_login() {
+        return Err(NotAuthenticated("either a Lemmy token or a password to log in with is required".to_string()).into());
+    }
+
+    login(config, api).await?;
+    Ok(true)
+}
+
+/// Check that the server accepts the token, and that it belongs to the configured user.
+async fn verify(config: &Configuration, api: &Api) -> Result<()> {
+    let person = match api.my_person(config).await {
+        Ok(person) => person,
+        Err(error) if error.is_auth_failure() => {
+            return Err(NotAuthenticated(format!("the server rejected the token: {error}")).into());
+        }
+        Err(error) => return Err(anyhow!("Failed to check the token: {error}")),
+    };
+
+    let person = person.ok_or(NotAuthenticated("the server did not recognise the token".to_string()))?;
+
+    if !person.qualified_name().eq_ignore_ascii_case(config.canonical_username()) {
+        return Err(NotAuthenticated(format!("the token belongs to {}, not to {}", person.qualified_name(), config.canonical_username())).into());
+    }
+    api.set_person_id(person.id);
+
+    Ok(())
+}
+
+/// Make sure there is a working token for the configured user before anything else happens.
+///
+/// If a configured or cached token turns out not to work and a password is available, this logs
+/// in again once.
+pub(crate) async fn authenticate(config: &Configuration, api: &Api) -> Result<()> {
+    // The way the token is sent depends on the version
+    crate::version::detect(api).await?;
+
+    let logged_in = pick_token(config, api).await?;
+
+    match verify(config, api).await {
+        Err(error) if !logged_in && config.can_login() && error.is::<NotAuthenticated>() => {
+            eprintln!("{error}, logging in again");
+            login(config, api).await?;
+            verify(config, api).await
+        }
+        result => result,
+    }
+}
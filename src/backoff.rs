@@ -0,0 +1,99 @@
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use reqwest::header::RETRY_AFTER;
+use reqwest::Response;
+use crate::configuration::Configuration;
+
+/// Decides how long to wait before retrying a failed request.
+///
+/// Waits grow exponentially from ten times the configured sleep time, with random jitter so that
+/// several clients hitting the same rate limit don't all come back at the same moment. A
+/// `Retry-After` sent by the server takes precedence over the computed wait.
+pub(crate) struct Backoff {
+    base: Duration,
+    ceiling: Duration,
+    max_attempts: u32,
+}
+
+impl Backoff {
+    pub fn new(config: &Configuration) -> Self {
+        Self {
+            base: Duration::from_millis(config.sleep_time.saturating_mul(10)),
+            ceiling: Duration::from_secs(config.backoff_ceiling),
+            max_attempts: config.max_attempts,
+        }
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// The time to wait after the given (1-based) attempt failed, or `None` if the request should
+    /// not be retried anymore.
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+
+        if let Some(retry_after) = retry_after {
+            // Retrying before the server is ready for it will only fail again
+            return (retry_after <= self.ceiling).then_some(retry_after);
+        }
+
+        let exponential = self.base.saturating_mul(2u32.saturating_pow(attempt - 1)).min(self.ceiling);
+
+        // Wait somewhere between half and all of the exponential delay
+        let jitter = exponential.mul_f64(fastrand::f64() / 2.0);
+        Some(exponential - jitter)
+    }
+}
+
+/// Read the `Retry-After` header of a response, which holds either a number of seconds or a date.
+pub(crate) fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?.with_timezone(&Utc);
+    Some((date - Utc::now()).to_std().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::Backoff;
+
+    fn backoff() -> Backoff {
+        Backoff { base: Duration::from_secs(1), ceiling: Duration::from_secs(10), max_attempts: 5 }
+    }
+
+    #[test]
+    fn grows_with_jitter_up_to_the_ceiling() {
+        let backoff = backoff();
+
+        for (attempt, full) in [(1, 1), (2, 2), (3, 4), (4, 8)] {
+            let full = Duration::from_secs(full).min(backoff.ceiling);
+            let delay = backoff.delay(attempt, None).unwrap();
+            assert!(delay > full / 2 && delay <= full, "attempt {attempt} waited {delay:?}");
+        }
+
+        let capped = Backoff { max_attempts: 20, ..backoff };
+        assert!(capped.delay(10, None).unwrap() <= capped.ceiling);
+    }
+
+    #[test]
+    fn follows_retry_after_within_the_ceiling() {
+        let backoff = backoff();
+
+        assert_eq!(backoff.delay(1, Some(Duration::from_secs(7))), Some(Duration::from_secs(7)));
+        assert_eq!(backoff.delay(1, Some(Duration::from_secs(60))), None);
+    }
+
+    #[test]
+    fn gives_up_after_the_last_attempt() {
+        assert_eq!(backoff().delay(5, None), None);
+        assert_eq!(backoff().delay(5, Some(Duration::from_secs(1))), None);
+    }
+}
@@ -1,16 +1,21 @@
 use std::fmt::{Display, Formatter};
 use chrono::{DateTime, Utc};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 /// An object representing a single comment. Simplified.
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub(crate) struct Comment {
     pub(crate) id: i64,
+    /// The federated ID of the comment, usually its URL on the home instance.
+    pub(crate) ap_id: String,
+    pub(crate) post_id: i64,
     pub(crate) content: String,
     pub(crate) removed: bool,
     pub(crate) deleted: Option<bool>,
-    #[serde(deserialize_with = "crate::helper::deserialize_date")]
+    #[serde(alias = "published_at", deserialize_with = "crate::helper::deserialize_date")]
     pub(crate) published: DateTime<Utc>,
+    #[serde(default, alias = "updated_at", deserialize_with = "crate::helper::deserialize_optional_date")]
+    pub(crate) updated: Option<DateTime<Utc>>,
 }
 
 impl Comment {
@@ -20,7 +25,10 @@ impl Comment {
     }
     /// A subselection of a comment's contents, for use in debugging and printing.
     pub fn short_content(&self) -> &str {
-        &self.content[..100.min(self.content.len())]
+        match self.content.char_indices().nth(100) {
+            Some((end, _)) => &self.content[..end],
+            None => &self.content,
+        }
     }
 }
 
@@ -0,0 +1,31 @@
+use std::fmt::{Display, Formatter};
+use serde::{Deserialize, Serialize};
+
+/// An object representing the community a post or comment was placed in. Simplified.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct Community {
+    pub(crate) id: i64,
+    /// The short name of the community, without the instance.
+    pub(crate) name: String,
+    /// The federated ID of the community, i.e. https://lemmy.example/c/name
+    #[serde(alias = "ap_id")]
+    pub(crate) actor_id: String,
+}
+
+impl Community {
+    /// The instance the community lives on, taken from its federated ID.
+    pub fn instance(&self) -> &str {
+        crate::helper::instance_of(&self.actor_id)
+    }
+
+    /// The community name in the `name@instance` form users type into search bars.
+    pub fn qualified_name(&self) -> String {
+        format!("{}@{}", self.name, self.instance())
+    }
+}
+
+impl Display for Community {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "!{}", self.qualified_name())
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Context, Result};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches};
+use serde::Deserialize;
+use crate::configuration::{Command, Configuration};
+use crate::rules::{CommunityRule, Pattern, Retention};
+
+/// The settings of a single account in the configuration file. Every setting is optional; missing
+/// settings fall back to the `[defaults]` table, and then to the command line defaults.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Profile {
+    username: Option<String>,
+    lemmy_token: Option<String>,
+    lemmy_password: Option<String>,
+    totp_2fa_token: Option<String>,
+    days_to_keep: Option<u64>,
+    communities: Option<BTreeMap<String, Retention>>,
+    keep_favourites: Option<bool>,
+    keep_min_score: Option<i64>,
+    keep_min_replies: Option<i64>,
+    low_score_threshold: Option<i64>,
+    low_score_days: Option<u64>,
+    pinned_file: Option<PathBuf>,
+    keep_newest_comments: Option<usize>,
+    keep_newest_posts: Option<usize>,
+    keep_patterns: Option<Vec<Pattern>>,
+    delete_patterns: Option<Vec<Pattern>>,
+    keep_upvotes: Option<bool>,
+    keep_downvotes: Option<bool>,
+    edit_then_delete: Option<bool>,
+    edit_text: Option<String>,
+    sleep_time: Option<u64>,
+    max_attempts: Option<u32>,
+    backoff_ceiling: Option<u64>,
+    archive_dir: Option<PathBuf>,
+    state_dir: Option<PathBuf>,
+    interval: Option<u64>,
+    cron: Option<String>,
+}
+
+/// A configuration file, holding named account profiles.
+///
+/// ```toml
+/// [defaults]
+/// days_to_keep = 14
+///
+/// [profiles.main]
+/// username = "me@lemmy.example"
+/// lemmy_password = "hunter2"
+/// keep_favourites = true
+///
+/// [profiles.main.communities]
+/// "support@lemmy.example" = "never"
+/// "memes@lemmy.example" = 2
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ConfigFile {
+    #[serde(default)]
+    defaults: Profile,
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+}
+
+impl ConfigFile {
+    /// The configuration file used when none has been passed explicitly, if it exists.
+    fn default_path() -> Option<PathBuf> {
+        let path = dirs::config_dir()?.join("distenourize").join("config.toml");
+        path.exists().then_some(path)
+    }
+
+    fn read(path: &Path) -> Result<Self> {
+        let contents = read_to_string(path)
+            .with_context(|| format!("Failed to read configuration file {}", path.display()))?;
+
+        toml::from_str(&contents)
+            .with_context(|| format!("Invalid configuration file {}", path.display()))
+    }
+
+    /// Combine the command line with every selected profile in the file.
+    fn configurations(&self, mut cli: Configuration, matches: &ArgMatches, path: &Path) -> Result<Vec<Configuration>> {
+        for name in &cli.profile {
+            if !self.profiles.contains_key(name) {
+                return Err(anyhow!("Profile {name} does not exist in {}", path.display()));
+            }
+        }
+
+        // A USERNAME set by the desktop session must not become the account of a profile without one
+        if !is_explicit(matches, "username") {
+            cli.username = None;
+        }
+
+        let mut configurations = vec![];
+        for (name, profile) in &self.profiles {
+            if !cli.profile.is_empty() && !cli.profile.contains(name) {
+                continue;
+            }
+
+            let mut config = cli.clone();
+            self.defaults.apply(&mut config, matches)
+                .with_context(|| format!("Invalid defaults in {}", path.display()))?;
+            profile.apply(&mut config, matches)
+                .with_context(|| format!("Invalid profile {name} in {}", path.display()))?;
+            config.profile_name = Some(name.clone());
+
+            if config.username.is_none() {
+                return Err(anyhow!("Profile {name} in {} has no username", path.display()));
+            }
+
+            configurations.push(config);
+        }
+
+        if configurations.is_empty() {
+            return Err(anyhow!("No profiles to process in {}", path.display()));
+        }
+        // IDs only mean something on one instance, pinning them for every account would pin unrelated items
+        if configurations.len() > 1 && matches!(cli.command, Some(Command::Pin(_) | Command::Unpin(_))) {
+            return Err(anyhow!("pin and unpin work on a single account, but {} profiles are selected, pick one with --profile", configurations.len()));
+        }
+        if configurations.len() > 1 && is_explicit(matches, "username") {
+            return Err(anyhow!("--username would replace the account of {} profiles, pick one with --profile", configurations.len()));
+        }
+
+        Ok(configurations)
+    }
+}
+
+/// The settings that say which account a profile is for. Environment variables like `USERNAME` are
+/// often set for other reasons, so only the command line overrides these.
+const ACCOUNT_SETTINGS: [&str; 4] = ["username", "lemmy_token", "lemmy_password", "totp_2fa_token"];
+
+/// Check whether an option was given on the command line or in the environment, in which case it
+/// takes precedence over the configuration file.
+fn is_explicit(matches: &ArgMatches, id: &str) -> bool {
+    match matches.value_source(id) {
+        Some(ValueSource::CommandLine) => true,
+        Some(ValueSource::EnvVariable) => !ACCOUNT_SETTINGS.contains(&id),
+        _ => false,
+    }
+}
+
+/// Copy the settings in a profile into a configuration, unless they were set explicitly.
+macro_rules! apply_profile {
+    ($config:expr, $matches:expr, $profile:expr, values: [$($value:ident),*], options: [$($option:ident),*]) => {
+        $(
+            if let Some(value) = &$profile.$value {
+                if !is_explicit($matches, stringify!($value)) {
+                    $config.$value = value.clone();
+                }
+            }
+        )*
+        $(
+            if let Some(value) = &$profile.$option {
+                if !is_explicit($matches, stringify!($option)) {
+                    $config.$option = Some(value.clone());
+                }
+            }
+        )*
+    };
+}
+
+impl Profile {
+    fn apply(&self, config: &mut Configuration, matches: &ArgMatches) -> Result<()> {
+        apply_profile!(config, matches, self,
+            values: [days_to_keep, keep_favourites, keep_upvotes, keep_downvotes, low_score_days,
+                keep_newest_comments, keep_newest_posts, keep_patterns, delete_patterns, edit_then_delete, edit_text, sleep_time, max_attempts, backoff_ceiling, archive_dir,
+                state_dir, interval],
+            options: [username, lemmy_token, lemmy_password, totp_2fa_token, pinned_file, keep_min_score,
+                keep_min_replies, low_score_threshold, cron]);
+
+        if let Some(communities) = &self.communities {
+            if !is_explicit(matches, "community_rules") {
+                config.community_rules = communities.iter()
+                    .map(|(community, &retention)| Ok(CommunityRule { community: community.parse()?, retention }))
+                    .collect::<Result<_>>()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse the command line, and combine it with the configuration file if there is one.
+///
+/// This returns one configuration per account to process. Without a configuration file, that is
+/// just the command line configuration. With one, it is every profile in the file (or the ones
+/// selected with `--profile`), with command line options overriding the file.
+pub(crate) fn load() -> Result<Vec<Configuration>> {
+    let matches = Configuration::command().get_matches();
+    let cli = Configuration::from_arg_matches(&matches)?;
+
+    // The default configuration file is only picked up when no account was given on the command line
+    let path = match &cli.config {
+        Some(path) => Some(path.clone()),
+        None if is_explicit(&matches, "username") => None,
+        None => ConfigFile::default_path(),
+    };
+    let Some(path) = path else {
+        if cli.username.is_none() {
+            return Err(anyhow!("A username is required, either on the command line or in a configuration file"));
+        }
+        return Ok(vec![cli]);
+    };
+
+    ConfigFile::read(&path)?.configurations(cli, &matches, &path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use anyhow::Result;
+    use clap::{CommandFactory, FromArgMatches};
+    use crate::configuration::Configuration;
+    use crate::rules::Retention;
+    use super::ConfigFile;
+
+    const FILE: &str = r#"
+        [defaults]
+        days_to_keep = 30
+        sleep_time = 500
+
+        [profiles.a]
+        username = "a@one.example"
+        days_to_keep = 7
+
+        [profiles.a.communities]
+        "memes@one.example" = "now"
+
+        [profiles.b]
+        username = "b@two.example"
+    "#;
+
+    fn load(args: &[&str]) -> Result<Vec<Configuration>> {
+        let matches = Configuration::command().try_get_matches_from(["lemmy-old-comment-purge"].iter().chain(args))?;
+        let cli = Configuration::from_arg_matches(&matches)?;
+        let file: ConfigFile = toml::from_str(FILE)?;
+
+        file.configurations(cli, &matches, Path::new("config.toml"))
+    }
+
+    #[test]
+    fn profiles_override_defaults_which_override_built_in_defaults() {
+        let [a, b] = &load(&[]).unwrap()[..] else { panic!("expected two profiles") };
+
+        assert_eq!((a.username.as_deref(), a.days_to_keep, a.sleep_time), (Some("a@one.example"), 7, 500));
+        assert_eq!((b.username.as_deref(), b.days_to_keep, b.sleep_time), (Some("b@two.example"), 30, 500));
+        assert_eq!(a.community_rules[0].retention, Retention::Immediately);
+        assert!(b.community_rules.is_empty());
+        assert_eq!((a.low_score_days, b.low_score_days), (1, 1));
+    }
+
+    #[test]
+    fn command_line_overrides_profiles() {
+        let [a, b] = &load(&["--days-to-keep", "3", "--sleep-time", "0", "-r", "memes@one.example=never"]).unwrap()[..] else {
+            panic!("expected two profiles")
+        };
+
+        assert_eq!((a.days_to_keep, a.sleep_time), (3, 0));
+        assert_eq!((b.days_to_keep, b.sleep_time), (3, 0));
+        assert_eq!(a.community_rules[0].retention, Retention::Never);
+    }
+
+    #[test]
+    fn command_line_username_needs_a_single_profile() {
+        assert!(load(&["--username", "c@three.example"]).is_err());
+
+        let [a] = &load(&["--username", "c@three.example", "--profile", "a"]).unwrap()[..] else { panic!("expected one profile") };
+        assert_eq!((a.username.as_deref(), a.days_to_keep), (Some("c@three.example"), 7));
+    }
+
+    #[test]
+    fn unknown_profiles_are_rejected() {
+        assert!(load(&["--profile", "c"]).is_err());
+    }
+}
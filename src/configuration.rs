@@ -1,17 +1,74 @@
+use std::path::PathBuf;
 use std::time::Duration;
-use clap::Parser;
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand};
+use crate::delete::Target;
+use crate::helper::parse_moment;
+use crate::rules::{CommunityRule, Pattern};
 
-#[derive(Debug, Parser)]
+#[derive(Clone, Debug, Parser)]
 #[command(author = "Skull Giver", version, about = "Automatically delete old comments and posts", long_about = None)]
 pub(crate) struct Configuration {
+    /// A TOML file with account profiles. Defaults to ~/.config/distenourize/config.toml if no username is given.
+    #[arg(short = 'c', long, env = "DISTENOURIZE_CONFIG")]
+    pub(crate) config: Option<PathBuf>,
+    /// Only process these profiles from the configuration file. Defaults to all of them.
+    #[arg(short = 'P', long)]
+    pub(crate) profile: Vec<String>,
+    /// The name of the configuration file profile this configuration was built from, if any.
+    #[arg(skip)]
+    pub(crate) profile_name: Option<String>,
     #[arg(long, env)]
-    pub(crate) username: String,
+    pub(crate) username: Option<String>,
+    /// The JWT to authenticate with. Not needed when logging in with a password.
     #[arg(short, long, env)]
-    pub(crate) lemmy_token: String,
+    pub(crate) lemmy_token: Option<String>,
+    /// The password to log in with. Also used to log in again when the server rejects the token.
+    #[arg(short = 'p', long, env)]
+    pub(crate) lemmy_password: Option<String>,
+    /// The current two-factor authentication code, if the account uses 2FA. It is only valid for a short while, so unattended logins do not work with 2FA.
+    #[arg(long, env)]
+    pub(crate) totp_2fa_token: Option<String>,
     #[arg(short = 'k', long, env, default_value = "14")]
     pub(crate) days_to_keep: u64,
+    /// Override the days to keep for a community, as name@instance=days or ID=days. Use never or now instead of days to keep everything or nothing.
+    #[arg(short = 'r', long = "community-rule", env = "COMMUNITY_RULES", value_name = "RULE", value_delimiter = ',')]
+    pub(crate) community_rules: Vec<CommunityRule>,
     #[arg(short = 'f', long, env, default_value = "false")]
     pub(crate) keep_favourites: bool,
+    /// Keep posts and comments with at least this score, no matter how old they are.
+    #[arg(long, env, allow_negative_numbers = true)]
+    pub(crate) keep_min_score: Option<i64>,
+    /// Keep comments with at least this many replies, and posts with at least this many comments.
+    #[arg(long, env)]
+    pub(crate) keep_min_replies: Option<i64>,
+    /// Delete posts and comments with a score below this after LOW_SCORE_DAYS, instead of the usual days to keep.
+    #[arg(long, env, allow_negative_numbers = true)]
+    pub(crate) low_score_threshold: Option<i64>,
+    /// The amount of days to keep posts and comments with a score below LOW_SCORE_THRESHOLD.
+    #[arg(long, env, default_value = "1")]
+    pub(crate) low_score_days: u64,
+    /// Select posts and comments published before this moment (RFC 3339 or YYYY-MM-DD), instead of using the days to keep.
+    #[arg(long, env, value_parser = parse_moment)]
+    pub(crate) before: Option<DateTime<Utc>>,
+    /// Select posts and comments published at or after this moment (RFC 3339 or YYYY-MM-DD), instead of using the days to keep.
+    #[arg(long, env, value_parser = parse_moment)]
+    pub(crate) after: Option<DateTime<Utc>>,
+    /// The file listing posts and comments that are never deleted. Defaults to ~/.config/distenourize/USERNAME.pinned.
+    #[arg(long, env)]
+    pub(crate) pinned_file: Option<PathBuf>,
+    /// Keep this many of the newest comments, even if they are older than the days to keep.
+    #[arg(long, env, value_name = "COUNT", default_value = "0")]
+    pub(crate) keep_newest_comments: usize,
+    /// Keep this many of the newest posts, even if they are older than the days to keep.
+    #[arg(long, env, value_name = "COUNT", default_value = "0")]
+    pub(crate) keep_newest_posts: usize,
+    /// Keep posts and comments matching this regular expression, no matter what other rules say. Can be repeated.
+    #[arg(long = "keep-pattern", env = "KEEP_PATTERN", value_name = "KEEP_PATTERN")]
+    pub(crate) keep_patterns: Vec<Pattern>,
+    /// Delete posts and comments matching this regular expression regardless of age, unless they match a keep pattern. Can be repeated.
+    #[arg(long = "delete-pattern", env = "DELETE_PATTERN", value_name = "DELETE_PATTERN")]
+    pub(crate) delete_patterns: Vec<Pattern>,
     #[arg(short = 'u', long, env, default_value = "false")]
     pub(crate) keep_upvotes: bool,
     #[arg(short = 'd', long, env, default_value = "false")]
@@ -22,16 +79,101 @@ pub(crate) struct Configuration {
     pub(crate) edit_text: String,
     #[arg(short = 'w', long, env, default_value = "100")]
     pub(crate) sleep_time: u64,
+    /// How many times a request is sent before giving up on it, when the server is rate limiting or overwhelmed.
+    #[arg(long, env, default_value = "5")]
+    pub(crate) max_attempts: u32,
+    /// The longest time in seconds to wait before retrying a request.
+    #[arg(long, env, default_value = "300")]
+    pub(crate) backoff_ceiling: u64,
+    /// Crawl and filter the profile as usual, but only print what would be deleted. With restore, only print what would be restored.
+    #[arg(short = 'n', long, env, default_value = "false")]
+    pub(crate) dry_run: bool,
+    /// When doing a dry run, also write the deletion plan to this file as JSON.
+    #[arg(long, env, requires = "dry_run")]
+    pub(crate) dry_run_output: Option<PathBuf>,
+    /// Print every post and comment seen on the profile, with the rule that kept or selected it.
+    #[arg(short = 'x', long, env, default_value = "false")]
+    pub(crate) explain: bool,
+    /// The directory where a copy of every post and comment is stored before it is edited or deleted.
+    #[arg(short = 'a', long, env, default_value = "archive")]
+    pub(crate) archive_dir: PathBuf,
+    /// The directory holding the state database that remembers what earlier runs did.
+    #[arg(short = 's', long, env, default_value = "state")]
+    pub(crate) state_dir: PathBuf,
+    /// Crawl the whole profile from the first page, instead of skipping pages that only hold deleted items.
+    #[arg(long, env, default_value = "false")]
+    pub(crate) full_crawl: bool,
+    /// If another process is already running for this account, wait for it instead of exiting.
+    #[arg(long, env, default_value = "false")]
+    pub(crate) wait_for_lock: bool,
+    /// In daemon mode, the amount of minutes between the end of a run and the start of the next one.
+    #[arg(long, env, default_value = "30")]
+    pub(crate) interval: u64,
+    /// In daemon mode, run whenever this cron expression (with seconds) matches, instead of at an interval.
+    #[arg(long, env)]
+    pub(crate) cron: Option<String>,
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+}
+
+/// Actions other than the default cleanup run.
+#[derive(Clone, Debug, Subcommand)]
+pub(crate) enum Command {
+    /// Undo earlier runs using their archives: undelete items and put the original comment text back.
+    Restore(RestoreOptions),
+    /// Delete specific posts and comments right away, regardless of the retention rules.
+    Delete(DeleteOptions),
+    /// Protect posts and comments from ever being deleted, by adding them to the pinned file.
+    Pin(PinOptions),
+    /// Remove posts and comments from the pinned file.
+    Unpin(PinOptions),
+    /// Keep running, and clean up every profile on its own schedule (see --interval and --cron).
+    Daemon,
+}
+
+#[derive(Clone, Debug, Args)]
+pub(crate) struct RestoreOptions {
+    /// The archive files to restore from. Defaults to every archive of this user in the archive directory.
+    pub(crate) archives: Vec<PathBuf>,
+    /// Only restore these items (comma separated), as comment:ID, post:ID or a URL.
+    #[arg(long, value_name = "ITEM", value_delimiter = ',')]
+    pub(crate) ids: Vec<Target>,
+    /// Only restore items that were archived at or after this moment (RFC 3339 or YYYY-MM-DD).
+    #[arg(long, value_parser = parse_moment)]
+    pub(crate) since: Option<DateTime<Utc>>,
+    /// Only restore items that were archived before this moment (RFC 3339 or YYYY-MM-DD).
+    #[arg(long, value_parser = parse_moment)]
+    pub(crate) until: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, Args)]
+pub(crate) struct DeleteOptions {
+    /// The posts and comments to delete, as URLs or as comment:ID and post:ID.
+    pub(crate) items: Vec<Target>,
+    /// Also delete the items listed in this file, one per line. Empty lines and lines starting with # are ignored.
+    #[arg(long)]
+    pub(crate) file: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Args)]
+pub(crate) struct PinOptions {
+    /// The posts and comments to pin or unpin, as URLs or as comment:ID and post:ID.
+    #[arg(required = true)]
+    pub(crate) items: Vec<Target>,
 }
 
 impl Configuration {
     /// Turn a username, as passed in the configuration, into something usable for the API.
     pub fn canonical_username(&self) -> &str {
-        if self.username.starts_with('@') {
-            &self.username[1..]
-        } else {
-            &self.username[..]
-        }
+        // Always set after loading the configuration
+        let username = self.username.as_deref().unwrap_or_default();
+
+        username.strip_prefix('@').unwrap_or(username)
+    }
+
+    /// The instance the user's account lives on.
+    pub fn instance(&self) -> Option<&str> {
+        self.canonical_username().split_once('@').map(|(_name, instance)| instance)
     }
 
     /// Helper function that will pre-process a comment edit.
@@ -45,15 +187,25 @@ impl Configuration {
         tokio::time::sleep(Duration::from_millis(self.sleep_time)).await
     }
 
-    /// Wait for the server to recover (rate limits, overwhelmed server).
-    /// This will wait ten times the configured sleep time.
-    pub(crate) async fn wait_for_recovery(&self) {
-        for _ in 0..10 {
-            self.wait().await;
-        }
+    /// Whether an expired or rejected token can be replaced by logging in again.
+    pub(crate) fn can_login(&self) -> bool {
+        self.lemmy_password.is_some()
     }
 
-    pub(crate) fn auth_header(&self) -> String {
-        format!("Bearer {}", self.lemmy_token)
+    /// Whether items are selected by a fixed date range, rather than by their age.
+    pub(crate) fn has_date_range(&self) -> bool {
+        self.before.is_some() || self.after.is_some()
+    }
+
+    /// Check whether a moment falls within the configured date range.
+    pub(crate) fn in_date_range(&self, moment: DateTime<Utc>) -> bool {
+        self.before.is_none_or(|before| moment < before) && self.after.is_none_or(|after| moment >= after)
+    }
+
+    /// Make sure the configured credentials never show up in logs or files.
+    pub(crate) fn register_secrets(&self) {
+        for secret in [&self.lemmy_token, &self.lemmy_password].into_iter().flatten() {
+            crate::redact::register(secret);
+        }
     }
 }
\ No newline at end of file
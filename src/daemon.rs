@@ -0,0 +1,109 @@
+use std::str::FromStr;
+use std::time::Duration;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use crate::api::Api;
+use crate::configuration::Configuration;
+use crate::lock::AccountLock;
+use crate::redact::redact;
+use crate::shutdown::Shutdown;
+use crate::state::State;
+
+/// When a profile should run.
+enum Schedule {
+    /// Every so often, counted from the end of the previous run.
+    Interval(Duration),
+    /// Whenever the cron expression matches.
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    fn new(config: &Configuration) -> Result<Self> {
+        if let Some(expression) = &config.cron {
+            let schedule = cron::Schedule::from_str(expression)
+                .with_context(|| format!("Invalid cron expression '{expression}'"))?;
+            return Ok(Schedule::Cron(Box::new(schedule)));
+        }
+
+        if config.interval == 0 {
+            return Err(anyhow!("The interval between runs must be at least one minute"));
+        }
+
+        Ok(Schedule::Interval(Duration::from_secs(config.interval * 60)))
+    }
+
+    /// The first moment the profile should run after the given moment.
+    fn next_after(&self, moment: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Schedule::Interval(interval) => moment + *interval,
+            Schedule::Cron(schedule) => schedule.after(&moment).next()
+                // A cron expression that never matches again, i.e. one with a year in the past
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+        }
+    }
+}
+
+/// A profile managed by the daemon, along with everything that is kept between its runs.
+struct Job {
+    config: Configuration,
+    /// The client for the account, which keeps its connections and its token between runs.
+    api: Api,
+    schedule: Schedule,
+    state: State,
+    next_run: DateTime<Utc>,
+}
+
+/// Keep running the cleanup for every profile on its own schedule, until asked to stop.
+///
+/// Runs happen one at a time, so a run that takes longer than its interval never overlaps with the
+/// next one; the next run is scheduled from the moment the previous one finished. The state
+/// database, the connections and the login of every profile stay open between runs.
+pub(crate) async fn daemon(configurations: Vec<Configuration>, shutdown: &Shutdown) -> Result<()> {
+    let mut jobs = vec![];
+    for config in configurations {
+        let schedule = Schedule::new(&config)?;
+        let state = State::open(&config)?;
+        let api = Api::new(&config, shutdown)?;
+
+        // Interval profiles run right away, cron profiles wait for their first match
+        let next_run = match schedule {
+            Schedule::Interval(_) => Utc::now(),
+            Schedule::Cron(_) => schedule.next_after(Utc::now()),
+        };
+
+        println!("Scheduled {}, first run at {next_run}", config.canonical_username());
+        jobs.push(Job { config, api, schedule, state, next_run });
+    }
+
+    loop {
+        let Some(job) = jobs.iter_mut().min_by_key(|job| job.next_run) else {
+            return Ok(());
+        };
+
+        let wait = (job.next_run - Utc::now()).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = shutdown.wait() => {
+                println!("Daemon stopped");
+                return Ok(());
+            }
+        }
+
+        println!("Starting scheduled run for {}", job.config.canonical_username());
+        let result = match AccountLock::acquire(&job.config, shutdown).await {
+            Ok(_lock) => crate::cleanup(&job.config, &job.api, &job.state, shutdown).await,
+            Err(error) => Err(error),
+        };
+        if let Err(error) = result {
+            eprintln!("Scheduled run for {} failed: {}", job.config.canonical_username(), redact(&format!("{error:#}")));
+        }
+
+        if shutdown.requested() {
+            println!("Daemon stopped");
+            return Ok(());
+        }
+
+        job.next_run = job.schedule.next_after(Utc::now());
+        println!("Next run for {} at {}", job.config.canonical_username(), job.next_run);
+    }
+}
@@ -0,0 +1,343 @@
+use std::fmt::{Display, Formatter};
+use chrono::{DateTime, Utc};
+use crate::api::{CommentView, PostView};
+use crate::archive::ItemKind;
+use crate::community::Community;
+use crate::configuration::Configuration;
+use crate::rules::{CommunityRule, Pattern, Retention};
+use crate::within_days;
+
+/// The reason an item on the profile was not selected for deletion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum KeptBecause {
+    /// The item has already been deleted.
+    AlreadyDeleted,
+    /// The item is in the pinned file.
+    Pinned,
+    /// The text of the item matches a keep pattern.
+    KeepPattern { pattern: Pattern, matched: String },
+    /// The user upvoted the item and upvotes are being kept.
+    Upvoted,
+    /// The user downvoted the item and downvotes are being kept.
+    Downvoted,
+    /// The user saved the item and favourites are being kept.
+    Saved,
+    /// The item has a score at or above the configured minimum.
+    HighScore(i64),
+    /// The item has at least the configured amount of replies (or comments, for posts).
+    ManyReplies(i64),
+    /// The item is newer than the configured amount of days to keep.
+    TooNew,
+    /// The item was published outside the configured date range.
+    OutsideDateRange,
+    /// The item is one of the newest items of its kind, and that amount is being kept.
+    AmongNewest(ItemKind),
+    /// The item was posted in a community whose items are never deleted.
+    CommunityNever(CommunityRule),
+    /// The item is newer than the amount of days to keep configured for its community.
+    TooNewForCommunity(CommunityRule),
+}
+
+impl KeptBecause {
+    /// The configuration rule that produced this decision.
+    pub fn rule(&self) -> String {
+        match self {
+            KeptBecause::AlreadyDeleted => "deleted items are skipped".to_string(),
+            KeptBecause::Pinned => "pinned_file".to_string(),
+            KeptBecause::KeepPattern { pattern, .. } => format!("keep_pattern {pattern}"),
+            KeptBecause::Upvoted => "keep_upvotes".to_string(),
+            KeptBecause::Downvoted => "keep_downvotes".to_string(),
+            KeptBecause::Saved => "keep_favourites".to_string(),
+            KeptBecause::HighScore(_) => "keep_min_score".to_string(),
+            KeptBecause::ManyReplies(_) => "keep_min_replies".to_string(),
+            KeptBecause::TooNew => "days_to_keep".to_string(),
+            KeptBecause::OutsideDateRange => "before and after".to_string(),
+            KeptBecause::AmongNewest(kind) => format!("keep_newest_{}s", kind.as_str()),
+            KeptBecause::CommunityNever(rule) | KeptBecause::TooNewForCommunity(rule) => format!("community rule {rule}"),
+        }
+    }
+}
+
+impl Display for KeptBecause {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            KeptBecause::AlreadyDeleted => "already deleted".to_string(),
+            KeptBecause::Pinned => "pinned".to_string(),
+            KeptBecause::KeepPattern { matched, .. } => format!("contains '{matched}'"),
+            KeptBecause::Upvoted => "upvoted".to_string(),
+            KeptBecause::Downvoted => "downvoted".to_string(),
+            KeptBecause::Saved => "saved".to_string(),
+            KeptBecause::HighScore(score) => format!("score {score}"),
+            KeptBecause::ManyReplies(replies) => format!("{replies} replies"),
+            KeptBecause::TooNew | KeptBecause::TooNewForCommunity(_) => "too new".to_string(),
+            KeptBecause::AmongNewest(kind) => format!("one of the newest {}s", kind.as_str()),
+            KeptBecause::OutsideDateRange => "outside the date range".to_string(),
+            KeptBecause::CommunityNever(_) => "community is never cleaned".to_string(),
+        };
+
+        write!(f, "{reason} ({})", self.rule())
+    }
+}
+
+/// The reason an item on the profile was selected for deletion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum SelectedBecause {
+    /// The text of the item matches a delete pattern, which overrides every keep rule but the keep patterns.
+    DeletePattern { pattern: Pattern, matched: String },
+    /// The item is older than the configured amount of days to keep, and matches no keep rule.
+    TooOld,
+    /// The item is older than the amount of days to keep configured for its community.
+    TooOldForCommunity(CommunityRule),
+    /// The item was posted in a community whose items are deleted right away.
+    CommunityImmediately(CommunityRule),
+    /// The item was published within the configured date range, which replaces the age rules.
+    InDateRange,
+    /// The item has a score below the low score threshold, and is older than the days to keep such items.
+    LowScore(i64),
+}
+
+impl SelectedBecause {
+    /// Whether the item was selected only because of its age, as opposed to a rule that deletes it
+    /// sooner than that.
+    pub fn is_age_based(&self) -> bool {
+        matches!(self, SelectedBecause::TooOld | SelectedBecause::TooOldForCommunity(_))
+    }
+}
+
+impl Display for SelectedBecause {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SelectedBecause::DeletePattern { pattern, matched } => write!(f, "contains '{matched}' (delete_pattern {pattern})"),
+            SelectedBecause::TooOld => write!(f, "matches no keep rule"),
+            SelectedBecause::TooOldForCommunity(rule) => write!(f, "too old (community rule {rule})"),
+            SelectedBecause::CommunityImmediately(rule) => write!(f, "community is always cleaned (community rule {rule})"),
+            SelectedBecause::InDateRange => write!(f, "inside the date range (before and after)"),
+            SelectedBecause::LowScore(score) => write!(f, "score {score} (low_score_threshold)"),
+        }
+    }
+}
+
+/// The properties of a post or comment that the rules look at.
+struct Facts<'a> {
+    my_vote: Option<i64>,
+    saved: bool,
+    published: DateTime<Utc>,
+    community: &'a Community,
+    score: i64,
+    /// The amount of replies to a comment, or comments on a post.
+    replies: i64,
+    /// The text the patterns are matched against: the content of a comment, or the title, body and URL of a post.
+    text: Vec<&'a str>,
+}
+
+/// The outcome of running the configured rules against a single item.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Decision {
+    /// The item will be left alone.
+    Kept(KeptBecause),
+    /// The item will be deleted.
+    Selected(SelectedBecause),
+}
+
+impl Decision {
+    /// Decide what should happen to a comment seen on the profile.
+    pub fn for_comment(config: &Configuration, view: &CommentView) -> Self {
+        if view.comment.deleted == Some(true) {
+            return Decision::Kept(KeptBecause::AlreadyDeleted);
+        }
+
+        Self::by_rules(config, Facts {
+            my_vote: view.my_vote,
+            saved: view.saved,
+            published: view.comment.published,
+            community: &view.community,
+            score: view.counts.score,
+            replies: view.counts.child_count,
+            text: vec![&view.comment.content],
+        })
+    }
+
+    /// Decide what should happen to a post seen on the profile.
+    pub fn for_post(config: &Configuration, view: &PostView) -> Self {
+        if view.deleted == Some(true) || view.post.deleted {
+            return Decision::Kept(KeptBecause::AlreadyDeleted);
+        }
+
+        Self::by_rules(config, Facts {
+            my_vote: view.my_vote,
+            saved: view.saved,
+            published: view.post.published,
+            community: &view.community,
+            score: view.counts.score,
+            replies: view.counts.comments,
+            text: [Some(&view.post.name), view.post.body.as_ref(), view.post.url.as_ref()]
+                .into_iter()
+                .flatten()
+                .map(String::as_str)
+                .collect(),
+        })
+    }
+
+    /// The rules shared between posts and comments, in order of precedence.
+    fn by_rules(config: &Configuration, facts: Facts) -> Self {
+        for pattern in &config.keep_patterns {
+            if let Some(matched) = pattern.find(&facts.text) {
+                return Decision::Kept(KeptBecause::KeepPattern { pattern: pattern.clone(), matched: matched.to_string() });
+            }
+        }
+
+        for pattern in &config.delete_patterns {
+            if let Some(matched) = pattern.find(&facts.text) {
+                return Decision::Selected(SelectedBecause::DeletePattern { pattern: pattern.clone(), matched: matched.to_string() });
+            }
+        }
+
+        if config.keep_upvotes && facts.my_vote.filter(|&i| i > 0).is_some() {
+            return Decision::Kept(KeptBecause::Upvoted);
+        }
+
+        if config.keep_downvotes && facts.my_vote.filter(|&i| i < 0).is_some() {
+            return Decision::Kept(KeptBecause::Downvoted);
+        }
+
+        if config.keep_favourites && facts.saved {
+            return Decision::Kept(KeptBecause::Saved);
+        }
+
+        if config.keep_min_score.is_some_and(|minimum| facts.score >= minimum) {
+            return Decision::Kept(KeptBecause::HighScore(facts.score));
+        }
+
+        if config.keep_min_replies.is_some_and(|minimum| facts.replies >= minimum) {
+            return Decision::Kept(KeptBecause::ManyReplies(facts.replies));
+        }
+
+        // The first rule for the community overrides the global amount of days to keep
+        let rule = config.community_rules.iter().find(|rule| rule.community.matches(facts.community));
+
+        if let Some(rule) = rule.filter(|rule| rule.retention == Retention::Never) {
+            return Decision::Kept(KeptBecause::CommunityNever(rule.clone()));
+        }
+
+        if config.has_date_range() {
+            return match config.in_date_range(facts.published) {
+                true => Decision::Selected(SelectedBecause::InDateRange),
+                false => Decision::Kept(KeptBecause::OutsideDateRange),
+            };
+        }
+
+        if config.low_score_threshold.is_some_and(|threshold| facts.score < threshold)
+            && !within_days(facts.published, config.low_score_days) {
+            return Decision::Selected(SelectedBecause::LowScore(facts.score));
+        }
+
+        let Some(rule) = rule else {
+            if within_days(facts.published, config.days_to_keep) {
+                return Decision::Kept(KeptBecause::TooNew);
+            }
+            return Decision::Selected(SelectedBecause::TooOld);
+        };
+
+        match rule.retention {
+            Retention::Never => Decision::Kept(KeptBecause::CommunityNever(rule.clone())),
+            Retention::Immediately => Decision::Selected(SelectedBecause::CommunityImmediately(rule.clone())),
+            Retention::Days(days) if within_days(facts.published, days) => Decision::Kept(KeptBecause::TooNewForCommunity(rule.clone())),
+            Retention::Days(_) => Decision::Selected(SelectedBecause::TooOldForCommunity(rule.clone())),
+        }
+    }
+
+    pub fn is_selected(&self) -> bool {
+        matches!(self, Decision::Selected(_))
+    }
+
+    /// Keep the newest items that were only selected because of their age.
+    ///
+    /// This can only be decided once the whole profile has been seen, so it runs after the crawl
+    /// over every item that is still live.
+    pub fn keep_newest<T>(items: &mut [(T, Decision)], count: usize, kind: ItemKind, published: impl Fn(&T) -> DateTime<Utc>) {
+        let mut newest: Vec<_> = items.iter_mut().collect();
+        newest.sort_by_key(|(item, _)| std::cmp::Reverse(published(item)));
+
+        for (_, decision) in newest.into_iter().take(count) {
+            if matches!(decision, Decision::Selected(reason) if reason.is_age_based()) {
+                *decision = Decision::Kept(KeptBecause::AmongNewest(kind));
+            }
+        }
+    }
+}
+
+impl Display for Decision {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Decision::Kept(reason) => write!(f, "KEPT, {reason}"),
+            Decision::Selected(reason) => write!(f, "SELECTED, {reason}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Days, TimeZone, Utc};
+    use clap::Parser;
+    use crate::archive::ItemKind;
+    use crate::configuration::Configuration;
+    use crate::fixtures::comment;
+    use super::{Decision, KeptBecause, SelectedBecause};
+
+    fn config(args: &[&str]) -> Configuration {
+        Configuration::parse_from(["lemmy-old-comment-purge"].iter().chain(args))
+    }
+
+    fn days_ago(days: u64) -> DateTime<Utc> {
+        Utc::now() - Days::new(days)
+    }
+
+    #[test]
+    fn delete_pattern_beats_a_never_community() {
+        let config = config(&["--delete-pattern", "crypto", "-r", "!rust@lemmy.example=never"]);
+
+        let decision = Decision::for_comment(&config, &comment(1, "Buy crypto", 1, days_ago(1)));
+        assert!(matches!(decision, Decision::Selected(SelectedBecause::DeletePattern { .. })), "{decision}");
+
+        let decision = Decision::for_comment(&config, &comment(1, "Hello", 1, days_ago(100)));
+        assert!(matches!(decision, Decision::Kept(KeptBecause::CommunityNever(_))), "{decision}");
+    }
+
+    #[test]
+    fn keep_pattern_beats_a_delete_pattern() {
+        let config = config(&["--keep-pattern", "rust", "--delete-pattern", "crypto"]);
+
+        let decision = Decision::for_comment(&config, &comment(1, "rust, not crypto", 1, days_ago(100)));
+        assert!(matches!(decision, Decision::Kept(KeptBecause::KeepPattern { .. })), "{decision}");
+    }
+
+    #[test]
+    fn date_range_replaces_community_days() {
+        let config = config(&["--after", "2020-01-01", "--before", "2021-01-01", "-r", "!rust@lemmy.example=7"]);
+
+        let in_range = comment(1, "Hello", 1, Utc.with_ymd_and_hms(2020, 6, 1, 0, 0, 0).unwrap());
+        assert_eq!(Decision::for_comment(&config, &in_range), Decision::Selected(SelectedBecause::InDateRange));
+
+        let after_range = comment(1, "Hello", 1, Utc.with_ymd_and_hms(2022, 6, 1, 0, 0, 0).unwrap());
+        assert_eq!(Decision::for_comment(&config, &after_range), Decision::Kept(KeptBecause::OutsideDateRange));
+    }
+
+    #[test]
+    fn keep_newest_does_not_save_low_scores() {
+        let config = config(&["--low-score-threshold", "0", "--days-to-keep", "30"]);
+
+        let mut items: Vec<_> = [comment(1, "Hated", -3, days_ago(5)), comment(1, "Old", 1, days_ago(60))]
+            .into_iter()
+            .map(|view| {
+                let decision = Decision::for_comment(&config, &view);
+                (view, decision)
+            })
+            .collect();
+        assert_eq!(items[0].1, Decision::Selected(SelectedBecause::LowScore(-3)));
+        assert_eq!(items[1].1, Decision::Selected(SelectedBecause::TooOld));
+
+        Decision::keep_newest(&mut items, 2, ItemKind::Comment, |view| view.comment.published);
+        assert_eq!(items[0].1, Decision::Selected(SelectedBecause::LowScore(-3)));
+        assert_eq!(items[1].1, Decision::Kept(KeptBecause::AmongNewest(ItemKind::Comment)));
+    }
+}
@@ -0,0 +1,223 @@
+use std::fmt::{Display, Formatter};
+use std::fs::read_to_string;
+use std::path::Path;
+use std::str::FromStr;
+use anyhow::{anyhow, Context, Error, Result};
+use url::Url;
+use crate::api::{is_fatal, Api, ContentView};
+use crate::archive::ItemKind;
+use crate::configuration::{Configuration, DeleteOptions};
+use crate::pinned::Pinned;
+use crate::shutdown::Shutdown;
+use crate::state::State;
+
+/// A post or comment to delete, as given on the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Target {
+    /// An item on the home instance, by its ID.
+    Id(ItemKind, i64),
+    /// The URL of an item, either on the home instance or on another instance.
+    Url(Url),
+}
+
+impl FromStr for Target {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+
+        if let Some((kind, id)) = value.split_once(':').filter(|(kind, _)| !kind.starts_with("http")) {
+            let kind = match kind {
+                "comment" => ItemKind::Comment,
+                "post" => ItemKind::Post,
+                _ => return Err(anyhow!("Invalid item '{value}', expected comment:ID, post:ID or a URL")),
+            };
+            let id = id.parse().map_err(|_| anyhow!("Invalid item '{value}', the ID is not a number"))?;
+            return Ok(Target::Id(kind, id));
+        }
+
+        Url::parse(value)
+            .map(Target::Url)
+            .map_err(|error| anyhow!("Invalid item '{value}', expected comment:ID, post:ID or a URL: {error}"))
+    }
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Target::Id(kind, id) => write!(f, "{}:{id}", kind.as_str()),
+            Target::Url(url) => write!(f, "{url}"),
+        }
+    }
+}
+
+impl Target {
+    /// Read items from a file, one per line. Empty lines and lines starting with `#` are ignored.
+    fn read_all(path: &Path) -> Result<Vec<Self>> {
+        let contents = read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        contents.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.parse().with_context(|| format!("Invalid line in {}", path.display())))
+            .collect()
+    }
+
+    /// The ID of the item on the home instance, if the target refers to it directly.
+    ///
+    /// Links to the home instance, like `https://lemmy.example/comment/1234`, carry their ID in
+    /// the path. Links to other instances have to be resolved by the home instance.
+    pub fn local_id(&self, home: &str) -> Option<(ItemKind, i64)> {
+        let url = match self {
+            Target::Id(kind, id) => return Some((*kind, *id)),
+            Target::Url(url) => url,
+        };
+
+        if !url.host_str().is_some_and(|host| host.eq_ignore_ascii_case(home)) {
+            return None;
+        }
+
+        let segments: Vec<_> = url.path_segments()?.filter(|segment| !segment.is_empty()).collect();
+        match segments[..] {
+            ["comment", id] => Some((ItemKind::Comment, id.parse().ok()?)),
+            ["post", id] => Some((ItemKind::Post, id.parse().ok()?)),
+            _ => None,
+        }
+    }
+
+    /// Check whether this target refers to the given item.
+    pub fn refers_to(&self, home: &str, kind: ItemKind, id: i64, ap_id: &str) -> bool {
+        match (self.local_id(home), self) {
+            (Some(local), _) => local == (kind, id),
+            (None, Target::Url(url)) => url.as_str() == ap_id,
+            (None, Target::Id(..)) => false,
+        }
+    }
+}
+
+/// Look up a target on the home instance.
+async fn find(config: &Configuration, api: &Api, target: &Target) -> Result<ContentView> {
+    let home = config.instance().ok_or(anyhow!("Invalid username"))?;
+
+    match target.local_id(home) {
+        Some((ItemKind::Comment, id)) => Ok(ContentView::Comment(api.comment(config, id).await?)),
+        Some((ItemKind::Post, id)) => Ok(ContentView::Post(api.post_by_id(config, id).await?)),
+        None => {
+            let Target::Url(url) = target else { unreachable!() };
+            api.resolve(config, url.as_str())
+                .await?
+                .ok_or(anyhow!("The URL is not a post or a comment"))
+        }
+    }
+}
+
+/// Delete specific posts and comments right away, regardless of the retention rules.
+///
+/// Every item is checked to belong to the configured user before anything happens to it. The
+/// items then go through the same archive, journal and deletion steps as a regular run.
+pub(crate) async fn delete(config: &Configuration, api: &Api, state: &State, shutdown: &Shutdown, options: &DeleteOptions) -> Result<()> {
+    let mut targets = options.items.clone();
+    if let Some(path) = &options.file {
+        targets.extend(Target::read_all(path)?);
+    }
+    if targets.is_empty() {
+        return Err(anyhow!("Nothing to delete, pass URLs or IDs on the command line or with --file"));
+    }
+
+    let username = config.canonical_username();
+    let pinned = Pinned::load(config)?;
+    let mut comments = vec![];
+    let mut posts = vec![];
+    let mut skipped = 0;
+
+    for target in &targets {
+        if shutdown.requested() {
+            return Err(anyhow!("Interrupted while looking up items, nothing has been changed"));
+        }
+
+        let found = match find(config, api, target).await {
+            Ok(found) => found,
+            Err(error) if is_fatal(&error) => return Err(error.context(format!("Failed to look up {target}"))),
+            Err(error) => {
+                eprintln!("Skipping {target}, looking it up failed: {error:#}");
+                skipped += 1;
+                continue;
+            }
+        };
+
+        match found {
+            ContentView::Comment(view) if !view.creator.qualified_name().eq_ignore_ascii_case(username) => {
+                eprintln!("Skipping comment {}, it was written by {}", view.comment.ap_id, view.creator.qualified_name());
+                skipped += 1;
+            }
+            ContentView::Comment(view) if view.comment.deleted == Some(true) => {
+                println!("Skipping comment {}, it has already been deleted", view.comment.ap_id);
+            }
+            ContentView::Comment(view) if pinned.contains(ItemKind::Comment, view.comment.id, &view.comment.ap_id) => {
+                eprintln!("Skipping comment {}, it has been pinned", view.comment.ap_id);
+                skipped += 1;
+            }
+            ContentView::Comment(view) => comments.push(view),
+            ContentView::Post(view) if !view.creator.qualified_name().eq_ignore_ascii_case(username) => {
+                eprintln!("Skipping post {}, it was written by {}", view.post.ap_id, view.creator.qualified_name());
+                skipped += 1;
+            }
+            ContentView::Post(view) if view.post.deleted => {
+                println!("Skipping post {}, it has already been deleted", view.post.ap_id);
+            }
+            ContentView::Post(view) if pinned.contains(ItemKind::Post, view.post.id, &view.post.ap_id) => {
+                eprintln!("Skipping post {}, it has been pinned", view.post.ap_id);
+                skipped += 1;
+            }
+            ContentView::Post(view) => posts.push(view),
+        }
+
+        config.wait().await;
+    }
+
+    if skipped > 0 {
+        eprintln!("Skipped {skipped}/{} items", targets.len());
+    }
+
+    // Leftovers of an interrupted cleanup are not ours to finish, the next cleanup run checks them again
+    let leftover_comments = state.journal_comments()?;
+    let leftover_posts = state.journal_posts()?;
+    let comments = crate::with_progress(comments, &leftover_comments, crate::comment_key);
+    let posts = crate::with_progress(posts, &leftover_posts, crate::post_key);
+    let untouched = leftover_comments.iter().filter(|(leftover, _)| !comments.iter().any(|(view, _)| view.comment.id == leftover.comment.id)).count()
+        + leftover_posts.iter().filter(|(leftover, _)| !posts.iter().any(|(view, _)| view.post.id == leftover.post.id)).count();
+    if untouched > 0 {
+        println!("{untouched} items left over from an interrupted run are not touched, the next cleanup run checks them again");
+    }
+
+    if config.dry_run {
+        return crate::print_deletion_plan(config, &comments, &posts);
+    }
+
+    crate::delete_items(config, api, state, shutdown, comments, posts).await
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+    use crate::archive::ItemKind;
+    use super::Target;
+
+    #[test]
+    fn parses_targets() {
+        assert_eq!("comment:12".parse::<Target>().unwrap(), Target::Id(ItemKind::Comment, 12));
+        assert_eq!(" post:34 ".parse::<Target>().unwrap(), Target::Id(ItemKind::Post, 34));
+        assert_eq!(
+            "https://lemmy.example/comment/56".parse::<Target>().unwrap(),
+            Target::Url(Url::parse("https://lemmy.example/comment/56").unwrap()),
+        );
+    }
+
+    #[test]
+    fn rejects_bad_targets() {
+        for value in ["comment:twelve", "user:12", "12", "comment", "lemmy.example/comment/56"] {
+            assert!(value.parse::<Target>().is_err(), "{value} should not parse");
+        }
+    }
+}
@@ -0,0 +1,31 @@
+//! Posts and comments shaped like the ones the Lemmy API returns, shared by the tests.
+
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use crate::api::CommentView;
+
+/// A comment by `me@lemmy.example` in `!rust@lemmy.example`, as JSON. The timestamp is passed as
+/// text, so that tests can hand in ones that don't parse.
+pub(crate) fn comment_json(id: i64, content: &str, score: i64, published: &str) -> serde_json::Value {
+    json!({
+        "comment": {
+            "id": id,
+            "ap_id": format!("https://lemmy.example/comment/{id}"),
+            "post_id": 1,
+            "content": content,
+            "removed": false,
+            "deleted": false,
+            "published": published,
+        },
+        "creator": { "id": 1, "name": "me", "actor_id": "https://lemmy.example/u/me" },
+        "community": { "id": 1, "name": "rust", "actor_id": "https://lemmy.example/c/rust" },
+        "counts": { "score": score, "child_count": 0 },
+        "saved": false,
+        "my_vote": null,
+    })
+}
+
+/// A comment by `me@lemmy.example` in `!rust@lemmy.example`.
+pub(crate) fn comment(id: i64, content: &str, score: i64, published: DateTime<Utc>) -> CommentView {
+    serde_json::from_value(comment_json(id, content, score, &published.to_rfc3339())).unwrap()
+}
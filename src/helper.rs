@@ -1,20 +1,105 @@
-use chrono::{DateTime, NaiveDateTime, Utc};
+use anyhow::anyhow;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
 use serde::{Deserialize, Deserializer};
 
-/// You can use this deserializer for any type that implements FromStr
-/// and the FromStr::Err implements Display
+/// Parse a timestamp sent by Lemmy, converted to UTC.
 ///
-/// Modified version of the helper from this StackOverflow example:
-/// https://stackoverflow.com/questions/57614558/how-to-use-a-custom-serde-deserializer-for-chrono-timestamps/57623355#57623355
+/// Lemmy 0.19 and later send RFC 3339 timestamps with an offset, while 0.18 sends naive timestamps
+/// that are in UTC. Both are accepted with any number of fractional digits, or none at all, and
+/// with either a `T` or a space between the date and the time.
+pub fn parse_timestamp(value: &str) -> anyhow::Result<DateTime<Utc>> {
+    let normalized = match value.as_bytes().get(10) {
+        Some(b' ') => format!("{}T{}", &value[..10], &value[11..]),
+        _ => value.to_string(),
+    };
+
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(&normalized) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+
+    // Offsets without a colon or without minutes, i.e. +0200 or +02
+    if let Ok(timestamp) = DateTime::parse_from_str(&normalized, "%Y-%m-%dT%H:%M:%S%.f%#z") {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+
+    NaiveDateTime::parse_from_str(&normalized, "%Y-%m-%dT%H:%M:%S%.f")
+        .map(|timestamp| timestamp.and_utc())
+        .map_err(|_| anyhow!("invalid timestamp '{value}'"))
+}
+
+/// Deserialize a timestamp sent by Lemmy, see [parse_timestamp].
 pub fn deserialize_date<'de, D>(deserializer: D) -> Result<DateTime<Utc>, <D as Deserializer<'de>>::Error>
     where
         D: Deserializer<'de>,
 {
-    let s: String = Deserialize::deserialize(deserializer)?;
-    let format = "%Y-%m-%dT%H:%M:%S%.6f";
-    NaiveDateTime::parse_and_remainder(&s, format)
-        .map(|(local_date, _remainder)| {
-            DateTime::from_naive_utc_and_offset(local_date, Utc)
-        })
-        .map_err(serde::de::Error::custom)
-}
\ No newline at end of file
+    let value: String = Deserialize::deserialize(deserializer)?;
+
+    parse_timestamp(&value).map_err(serde::de::Error::custom)
+}
+
+/// Like [deserialize_date], but for timestamps that may be missing or `null`, such as `updated`.
+pub fn deserialize_optional_date<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, <D as Deserializer<'de>>::Error>
+    where
+        D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct Wrapper(#[serde(deserialize_with = "deserialize_date")] DateTime<Utc>);
+
+    let value: Option<Wrapper> = Deserialize::deserialize(deserializer)?;
+    Ok(value.map(|Wrapper(date)| date))
+}
+
+/// Parse a moment passed on the command line, either as an RFC 3339 timestamp or as a plain date
+/// (`2023-01-31`), which stands for midnight UTC at the start of that day.
+pub fn parse_moment(value: &str) -> anyhow::Result<DateTime<Utc>> {
+    if let Ok(moment) = DateTime::parse_from_rfc3339(value) {
+        return Ok(moment.with_timezone(&Utc));
+    }
+
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| date.and_time(Default::default()).and_utc())
+        .map_err(|_| anyhow!("Invalid moment '{value}', expected an RFC 3339 timestamp or a date like 2023-01-31"))
+}
+
+/// Extract the instance (host name) from a federated ID such as `https://lemmy.example/u/name`.
+pub fn instance_of(actor_id: &str) -> &str {
+    let without_scheme = actor_id
+        .split_once("://")
+        .map_or(actor_id, |(_scheme, rest)| rest);
+
+    without_scheme.split('/').next().unwrap_or(without_scheme)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use super::parse_timestamp;
+
+    #[test]
+    fn parses_every_lemmy_timestamp_format() {
+        let moment = Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+        let cases = [
+            ("2024-01-02T03:04:05", moment),
+            ("2024-01-02T03:04:05Z", moment),
+            ("2024-01-02T05:04:05+02:00", moment),
+            ("2024-01-02T05:04:05+0200", moment),
+            ("2024-01-02T05:04:05+02", moment),
+            ("2024-01-02 03:04:05", moment),
+            ("2024-01-02T03:04:05.123456", moment + chrono::Duration::microseconds(123456)),
+            ("2024-01-02T03:04:05.123456789Z", moment + chrono::Duration::nanoseconds(123456789)),
+            ("2024-01-02 05:04:05.5+02:00", moment + chrono::Duration::milliseconds(500)),
+        ];
+
+        for (value, expected) in cases {
+            assert_eq!(parse_timestamp(value).unwrap(), expected, "{value}");
+        }
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        for value in ["", "yesterday", "2024-01-02", "2024-01-02T03:04:05Zjunk", "2024-13-02T03:04:05"] {
+            let error = parse_timestamp(value).unwrap_err();
+            assert!(error.to_string().contains(value), "{value}: {error}");
+        }
+    }
+}
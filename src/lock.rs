@@ -0,0 +1,104 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs::{create_dir_all, File, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+use anyhow::{anyhow, Context, Result};
+use crate::configuration::Configuration;
+use crate::shutdown::Shutdown;
+
+/// The error returned when another process is already working on the same account.
+#[derive(Debug)]
+pub(crate) struct AlreadyRunning {
+    pub(crate) username: String,
+    /// The process holding the lock, if it could be read from the lock file.
+    pub(crate) pid: Option<u32>,
+}
+
+impl Display for AlreadyRunning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.pid {
+            Some(pid) => write!(f, "Another process (PID {pid}) is already running for {}", self.username),
+            None => write!(f, "Another process is already running for {}", self.username),
+        }
+    }
+}
+
+impl Error for AlreadyRunning {}
+
+/// An advisory lock on an account, held for as long as this value lives.
+///
+/// The lock is a file in the state directory, locked with the operating system's file locking, so
+/// it is released automatically when the process dies. The file holds the PID of the process
+/// holding the lock, to tell users who is in the way.
+pub(crate) struct AccountLock {
+    _file: File,
+}
+
+impl AccountLock {
+    fn path(config: &Configuration) -> PathBuf {
+        config.state_dir.join(format!("{}.lock", config.canonical_username()))
+    }
+
+    /// Read the PID of the process holding the lock.
+    fn holder(file: &mut File) -> Option<u32> {
+        let mut contents = String::new();
+        file.rewind().ok()?;
+        file.read_to_string(&mut contents).ok()?;
+        contents.trim().parse().ok()
+    }
+
+    /// Take the lock for the configured account.
+    ///
+    /// If another process holds the lock, this either waits for it to be released or fails with
+    /// [AlreadyRunning], depending on the configuration. Waiting stops when the process is asked to stop.
+    pub async fn acquire(config: &Configuration, shutdown: &Shutdown) -> Result<Self> {
+        create_dir_all(&config.state_dir)
+            .with_context(|| format!("Failed to create state directory {}", config.state_dir.display()))?;
+
+        let path = Self::path(config);
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to open lock file {}", path.display()))?;
+
+        let mut announced = false;
+        loop {
+            match file.try_lock() {
+                Ok(()) => break,
+                Err(TryLockError::WouldBlock) => {
+                    let error = AlreadyRunning {
+                        username: config.canonical_username().to_string(),
+                        pid: Self::holder(&mut file),
+                    };
+                    if !config.wait_for_lock {
+                        return Err(error.into());
+                    }
+
+                    if !announced {
+                        println!("{error}, waiting for it to finish");
+                        announced = true;
+                    }
+                    tokio::select! {
+                        _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+                        _ = shutdown.wait() => return Err(anyhow!("Stopped waiting for the lock, shutdown requested")),
+                    }
+                }
+                Err(TryLockError::Error(error)) => {
+                    return Err(error).with_context(|| format!("Failed to lock {}", path.display()));
+                }
+            }
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", std::process::id())?;
+        file.flush()?;
+
+        Ok(Self { _file: file })
+    }
+}
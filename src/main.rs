@@ -1,17 +1,47 @@
-use clap::Parser;
-
 mod configuration;
+mod config_file;
+mod daemon;
+mod lock;
 mod api;
 mod comment;
 mod helper;
 mod post;
-
-use anyhow::{anyhow, Result};
+mod decision;
+mod rules;
+mod community;
+mod archive;
+mod restore;
+mod delete;
+mod pinned;
+mod state;
+mod shutdown;
+mod auth;
+mod person;
+mod backoff;
+mod version;
+mod redact;
+mod v4;
+#[cfg(test)]
+mod fixtures;
+
+use std::fs::write;
+use std::process::ExitCode;
+use anyhow::{anyhow, Context, Result};
 use chrono::{DateTime, Days, Utc};
-use crate::configuration::Configuration;
-use crate::api::{Api, CommentEditResponse, DeleteCommentBody, EditCommentBody, PostDeleteResponse, PostIdBody, ProfilePage};
+use serde::Serialize;
+use crate::configuration::{Command, Configuration, PinOptions};
+use crate::api::{is_fatal, is_interrupted, Api, ApiError, CommentView, PostView};
+use crate::auth::NotAuthenticated;
+use crate::lock::{AccountLock, AlreadyRunning};
+use crate::pinned::Pinned;
+use crate::archive::{Archive, ArchivedItem, ItemKind};
 use crate::comment::Comment;
+use crate::decision::{Decision, KeptBecause};
+use crate::rules::Retention;
 use crate::post::Post;
+use crate::redact::redact;
+use crate::shutdown::Shutdown;
+use crate::state::{JournalStatus, Outcome, State};
 
 /// Check if a date-time is within a certain date threshold
 pub fn within_days(date: DateTime<Utc>, days: u64) -> bool {
@@ -32,115 +62,202 @@ pub fn within_days(date: DateTime<Utc>, days: u64) -> bool {
 /// This could probably be written as an iterator to be more efficient, but for simplicity we fetch
 /// all of this before doing any modifications.
 ///
+/// Pages before the start page remembered in the state database only hold deleted items, so they
+/// are skipped unless a full crawl was requested, or the server pages with a cursor instead of
+/// page numbers. The remembered page is one before the first page with live items, as a margin
+/// for items that drop out of the listing. When more items drop out than that, live items can
+/// still end up on skipped pages, so the whole profile is crawled again once a week. Rules that
+/// depend on the whole profile, like keeping the newest items, are applied once the crawl is done.
+///
 /// This will either result two vectors (comments and posts) or an error indicating why these
 /// vectors couldn't be retrieved.
-async fn gather_data_from_profile(config: &Configuration) -> Result<(Vec<Comment>, Vec<Post>)> {
-    let api: Api = config.try_into()?;
+async fn gather_data_from_profile(config: &Configuration, api: &Api, state: &State, shutdown: &Shutdown) -> Result<(Vec<CommentView>, Vec<PostView>)> {
+    let pinned = Pinned::load(config)?;
 
     let mut comments = vec![];
     let mut posts = vec![];
 
-    let mut page = 1;
-    loop {
-        println!("Fetching comments, page {page}");
-        let fetch_path = api.format_api_call(&format!("user?username={username}&sort=Old&page={page}&limit=50&auth={auth}", username = config.canonical_username(), auth = config.lemmy_token));
+    let numbered = api.version().has_numbered_pages();
+    let full_crawl_due = numbered && !config.full_crawl && state.full_crawl_due()?;
+    if full_crawl_due {
+        println!("Crawling the whole profile, as it hasn't been in a while");
+    }
+    let mut page = if config.full_crawl || !numbered || full_crawl_due { 1 } else { state.start_page()? };
+    let full_crawl = page == 1;
+    let mut cursor = None;
+    if page > 1 {
+        println!("Skipping to page {page}, earlier pages only contain deleted items");
+    }
 
-        let results = match api.client
-            .get(&fetch_path)
-            .header("Authorization", config.auth_header())
-            .send().await {
-            Ok(response) => response,
-            Err(error) => {
-                Err(error)?
-            }
-        };
-        if !results.status().is_success() {
-            eprintln!("HTTP error ({}), impending error!", results.status());
+    // The first page holding something that hasn't been deleted yet, where the next run can start
+    let mut first_live_page = None;
+    let mut last_page = None;
+    let mut unreadable = 0;
+
+    loop {
+        if shutdown.requested() {
+            return Err(anyhow!("Interrupted while crawling the profile, nothing has been changed"));
         }
 
-        let results: ProfilePage = match results.json().await {
-            Ok(results) => results,
-            Err(error) => {
-                eprintln!("Aborting loop because of error: {error}");
-                break;
-            }
-        };
+        println!("Fetching comments, page {page}");
 
-        if results.comments.is_empty() && results.posts.is_empty() {
+        let results = api.profile_page(config, page, cursor.as_deref())
+            .await
+            .with_context(|| format!("Failed to fetch page {page} of the profile"))?;
+
+        if results.comments.is_empty() && results.posts.is_empty() && results.unreadable.is_empty() {
             break;
         }
+        last_page = Some(page);
 
-        for comment in results.comments {
-            // Skip deleted comments
-            if comment.comment.deleted == Some(true) {
-                continue;
-            }
-
-            // Skip upvotes if enabled
-            if config.keep_upvotes && comment.my_vote.filter(|&i| i > 0).is_some() {
-                continue;
-            }
-            // Skip downvotes if enabled
-            if config.keep_downvotes && comment.my_vote.filter(|&i| i < 0).is_some() {
-                continue;
-            }
-
-            // Keep saved
-            if config.keep_favourites && comment.saved {
-                continue;
-            }
+        // Unreadable items are left alone, and looked at again by the next run
+        for unreadable in &results.unreadable {
+            eprintln!("Skipping {unreadable}");
+            first_live_page.get_or_insert(page);
+        }
+        unreadable += results.unreadable.len();
 
-            // Stick to provided day limit
-            if within_days(comment.comment.published, config.days_to_keep) {
+        for comment in results.comments {
+            let decision = if state.is_deleted(ItemKind::Comment, comment.comment.id)? {
+                Decision::Kept(KeptBecause::AlreadyDeleted)
+            } else if pinned.contains(ItemKind::Comment, comment.comment.id, &comment.comment.ap_id) {
+                Decision::Kept(KeptBecause::Pinned)
+            } else {
+                Decision::for_comment(config, &comment)
+            };
+            if decision == Decision::Kept(KeptBecause::AlreadyDeleted) {
+                if config.explain {
+                    println!("{decision}: {}", comment.comment);
+                }
                 continue;
             }
 
-            comments.push(comment.comment);
+            first_live_page.get_or_insert(page);
+            comments.push((comment, decision));
         }
 
         for post in results.posts {
-            // Skip deleted posts
-            if post.deleted == Some(true) || post.post.deleted {
+            let decision = if state.is_deleted(ItemKind::Post, post.post.id)? {
+                Decision::Kept(KeptBecause::AlreadyDeleted)
+            } else if pinned.contains(ItemKind::Post, post.post.id, &post.post.ap_id) {
+                Decision::Kept(KeptBecause::Pinned)
+            } else {
+                Decision::for_post(config, &post)
+            };
+            if decision == Decision::Kept(KeptBecause::AlreadyDeleted) {
+                if config.explain {
+                    println!("{decision}: {}", post.post);
+                }
                 continue;
             }
 
-            // Keep upvoted posts
-            if config.keep_upvotes && post.my_vote.filter(|&i| i > 0).is_some() {
-                continue;
-            }
+            first_live_page.get_or_insert(page);
+            posts.push((post, decision));
+        }
 
-            // Keep downvoted posts
-            if config.keep_downvotes && post.my_vote.filter(|&i| i < 0).is_some() {
-                continue;
+        page += 1;
+        if !numbered {
+            match results.next_page {
+                Some(next) => cursor = Some(next),
+                None => break,
             }
+        }
 
-            // Keep favourites
-            if config.keep_favourites && post.saved {
-                continue;
-            }
+        config.wait().await;
+    }
 
-            // Stick to provided day limit
-            if within_days(post.post.published, config.days_to_keep) {
-                continue;
-            }
+    if unreadable > 0 {
+        eprintln!("Could not read {unreadable} items, they have been left alone");
+    }
+
+    Decision::keep_newest(&mut comments, config.keep_newest_comments, ItemKind::Comment, |view| view.comment.published);
+    Decision::keep_newest(&mut posts, config.keep_newest_posts, ItemKind::Post, |view| view.post.published);
 
-            posts.push(post.post);
+    if config.explain {
+        for (view, decision) in &comments {
+            println!("{decision}: {}", view.comment);
         }
+        for (view, decision) in &posts {
+            println!("{decision}: {}", view.post);
+        }
+    }
 
-        page += 1;
+    let comments = comments.into_iter().filter(|(_, decision)| decision.is_selected()).map(|(view, _)| view).collect();
+    let posts = posts.into_iter().filter(|(_, decision)| decision.is_selected()).map(|(view, _)| view).collect();
 
-        config.wait().await;
+    if !config.dry_run && numbered {
+        // Start a page early next time, in case items drop out of the listing and later ones shift
+        // back onto pages that would otherwise be skipped
+        let start_page = first_live_page.or(last_page).unwrap_or(1);
+        state.set_start_page((start_page - 1).max(1), full_crawl)?;
     }
 
     Ok((comments, posts))
 }
 
+/// An item to edit or delete, along with how far an interrupted run got with it.
+pub(crate) type Planned<T> = (T, JournalStatus);
+
+pub(crate) fn comment_key(view: &CommentView) -> (i64, &str) {
+    (view.comment.id, &view.comment.ap_id)
+}
+
+pub(crate) fn post_key(view: &PostView) -> (i64, &str) {
+    (view.post.id, &view.post.ap_id)
+}
+
+/// Attach the progress an interrupted run made to the items selected by this run.
+pub(crate) fn with_progress<T>(selected: Vec<T>, leftovers: &[Planned<T>], key: impl Fn(&T) -> (i64, &str)) -> Vec<Planned<T>> {
+    selected.into_iter()
+        .map(|item| {
+            let status = leftovers.iter()
+                .find(|(leftover, _)| key(leftover).0 == key(&item).0)
+                .map_or(JournalStatus::Pending, |(_, status)| *status);
+            (item, status)
+        })
+        .collect()
+}
+
+/// Merge the items selected by this run with the ones an interrupted run left in the journal.
+///
+/// Leftovers are checked again rather than trusted: pinned items are dropped, and so are items
+/// that were only planned and have not been selected again, i.e. because a rule was loosened.
+/// Comments that have already been overwritten are deleted regardless of the rules, as their text
+/// only survives in the archive. Returns the items to handle, and the IDs of the dropped leftovers.
+fn merge_leftovers<T>(kind: ItemKind, selected: Vec<T>, leftovers: Vec<Planned<T>>, pinned: &Pinned, key: impl Fn(&T) -> (i64, &str)) -> (Vec<Planned<T>>, Vec<i64>) {
+    let mut planned = with_progress(selected, &leftovers, &key);
+    let mut dropped = vec![];
+
+    for (item, status) in leftovers {
+        let (id, ap_id) = key(&item);
+        if planned.iter().any(|(selected, _)| key(selected).0 == id) {
+            continue;
+        }
+
+        if pinned.contains(kind, id, ap_id) {
+            match status {
+                JournalStatus::Edited => eprintln!("Not finishing {} {id} from an interrupted run, it has been pinned. Its original text can be put back with restore", kind.as_str()),
+                _ => println!("Not finishing {} {id} from an interrupted run, it has been pinned", kind.as_str()),
+            }
+            dropped.push(id);
+        } else if status == JournalStatus::Edited {
+            println!("Finishing {} {id} from an interrupted run, it has already been edited", kind.as_str());
+            planned.push((item, status));
+        } else {
+            println!("Not finishing {} {id} from an interrupted run, it is no longer selected", kind.as_str());
+            dropped.push(id);
+        }
+    }
+
+    (planned, dropped)
+}
+
 /// Delete a post.
 ///
 /// It will return `Ok(true)` for deletes than have been requested successfully, `Ok(false)` for
 /// deletes that have been requested but that the server did not flag as deleted in the response,
 /// and anything else to indicate a general error.
-async fn delete_post(config: &Configuration, post: &Post) -> Result<bool> {
+async fn delete_post(config: &Configuration, api: &Api, post: &Post) -> Result<bool> {
     if post.deleted {
         println!("BUG: request to delete deleted post");
         return Ok(true);
@@ -148,36 +265,9 @@ async fn delete_post(config: &Configuration, post: &Post) -> Result<bool> {
 
     config.wait().await;
 
-    let api: Api = config.try_into()?;
-
-    let url = api.format_api_call("post/delete");
-
-    let request = match api.client.post(url)
-        .header("Authorization", config.auth_header())
-        .json(&PostIdBody::new(post.id, config.lemmy_token.clone()))
-        .send()
-        .await {
-        Ok(ok) => ok,
-        Err(err) => Err(err)?
-    };
-
-    if !request.status().is_success() {
-        eprintln!("Delete failure for comment {}: {}", post.item_id(), request.status());
-    }
-
-    let response = request.text().await?;
-
-    let response: PostDeleteResponse = match serde_json::from_str(&response) {
-        Ok(response) => response,
-        Err(err) => {
-            eprintln!("Post delete parse failure: {err}");
-            eprintln!("Input that failed to parse was: {response}");
-
-            Err(err)?
-        }
-    };
+    let view = api.delete_post(config, post.id, true).await?;
 
-    response.post_view.deleted.ok_or(anyhow!("Failed to verify deletion"))
+    Ok(view.post.deleted)
 }
 
 
@@ -186,56 +276,19 @@ async fn delete_post(config: &Configuration, post: &Post) -> Result<bool> {
 /// This method will either return `Ok(true)` to indicate that the edit was successful, `Ok(false)`
 /// to indicate that the edit was successfully requested but the server did not apply the change,
 /// or anything else to indicate an error occurred.
-async fn edit_comment(config: &Configuration, comment: &Comment) -> Result<bool> {
+async fn edit_comment(config: &Configuration, api: &Api, comment: &Comment) -> Result<bool> {
     if comment.deleted == Some(true) {
         println!("Bug: request to edit deleted comment");
         return Ok(true);
     }
 
-    let api: Api = config.try_into()?;
-
-    let url = api.format_api_call("comment");
+    let view = api.edit_comment(config, comment.id, config.encoded_edit_text()).await?;
 
-    let mut tries = 3;
-
-    while tries > 0 {
-        let request = match api.client
-            .put(&url)
-            .header("Authorization", config.auth_header())
-            .json(&EditCommentBody::new(comment, config))
-            .send()
-            .await {
-            Ok(ok) => ok,
-            Err(err) => Err(err)?
-        };
-
-        let status_code = request.status();
-        if !status_code.is_success() {
-            // Ignore acceptable errors
-            if status_code.as_u16() == 503 {
-                // Server is overwhelmed
-                config.wait_for_recovery().await;
-                tries -= 1;
-                continue;
-            }
-
-            let body = request.text().await?;
-            eprintln!("Edit failure for comment {}: {}", comment.item_id(), status_code);
-            eprintln!("Edit result body: {body}");
-
-            return Err(anyhow!("Edit failure for comment {}: {}", comment.item_id(), status_code));
-        }
-
-        let response: CommentEditResponse = request.json().await?;
-
-        return if response.comment_view.comment.content != config.encoded_edit_text() {
-            Err(anyhow!("Edit did not succeed"))
-        } else {
-            Ok(true)
-        };
+    if view.comment.content != config.encoded_edit_text() {
+        Err(anyhow!("Edit did not succeed"))
+    } else {
+        Ok(true)
     }
-
-    Err(anyhow!("Failure"))
 }
 
 
@@ -244,7 +297,7 @@ async fn edit_comment(config: &Configuration, comment: &Comment) -> Result<bool>
 /// It will return `Ok(true)` for deletes than have been requested successfully, `Ok(false)` for
 /// deletes that have been requested but that the server did not flag as deleted in the response,
 /// and anything else to indicate a general error.
-async fn delete_comment(config: &Configuration, comment: &Comment) -> Result<bool> {
+async fn delete_comment(config: &Configuration, api: &Api, comment: &Comment) -> Result<bool> {
     if comment.deleted == Some(true) {
         eprintln!("Bug: tried to delete a deleted comment");
         return Ok(true);
@@ -252,59 +305,205 @@ async fn delete_comment(config: &Configuration, comment: &Comment) -> Result<boo
 
     config.wait().await;
 
-    if config.edit_then_delete {
-        edit_comment(config, comment).await?;
-    }
+    let view = api.delete_comment(config, comment.id, true).await?;
 
-    let api: Api = config.try_into()?;
+    view.comment.deleted.ok_or(anyhow!("Failed to verify deletion"))
+}
 
-    let url = api.format_api_call("comment/delete");
+/// The items a run would touch, as written to disk by a dry run.
+#[derive(Serialize)]
+struct DeletionPlan<'a> {
+    /// Whether comments would be overwritten before being deleted.
+    edit_then_delete: bool,
+    comment_count: usize,
+    post_count: usize,
+    comments: Vec<&'a CommentView>,
+    posts: Vec<&'a PostView>,
+}
 
-    let mut tries = 3;
+/// Print every item that would be edited or deleted, without making any write calls.
+///
+/// If a dry run output file has been configured, the plan is written there as JSON as well.
+pub(crate) fn print_deletion_plan(config: &Configuration, comments: &[Planned<CommentView>], posts: &[Planned<PostView>]) -> Result<()> {
+    println!("Dry run, nothing will be changed");
 
-    while tries > 0 {
-        let request = match api.client.post(&url)
-            .header("Authorization", config.auth_header())
-            .json(&DeleteCommentBody::new(comment, config))
-            .send().await {
-            Ok(ok) => ok,
-            Err(err) => Err(err)?
+    for (post, _status) in posts {
+        println!("Would delete: {} in {}", post.post, post.community);
+    }
+
+    for (comment, status) in comments {
+        let action = match status {
+            JournalStatus::Edited => "delete (edited by an interrupted run)",
+            _ if config.edit_then_delete => "edit and delete",
+            _ => "delete",
         };
+        println!("Would {action}: {} in {}", comment.comment, comment.community);
+    }
 
-        if !request.status().is_success() {
-            eprintln!("Delete failure for comment {}: {}", comment.item_id(), request.status());
+    println!("Would delete {} comments and {} posts", comments.len(), posts.len());
 
-            // Acceptable error, retry
-            if request.status().as_u16() == 503 {
-                config.wait_for_recovery().await;
-                tries -= 1;
-                continue;
-            }
+    if let Some(path) = &config.dry_run_output {
+        let plan = DeletionPlan {
+            edit_then_delete: config.edit_then_delete,
+            comment_count: comments.len(),
+            post_count: posts.len(),
+            comments: comments.iter().map(|(view, _)| view).collect(),
+            posts: posts.iter().map(|(view, _)| view).collect(),
+        };
+
+        write(path, redact(&serde_json::to_string_pretty(&plan)?))?;
+        println!("Deletion plan written to {}", path.display());
+    }
+
+    Ok(())
+}
+
+/// The exit code used when the server did not accept the credentials, so that monitoring can tell
+/// authentication problems apart from other failures.
+const EXIT_NOT_AUTHENTICATED: u8 = 3;
+
+/// The exit code used when another process is already working on the same account.
+const EXIT_ALREADY_RUNNING: u8 = 4;
+
+/// Pick the exit code for an error that ended a run.
+fn exit_code(error: &anyhow::Error) -> u8 {
+    let api_auth_failure = error.downcast_ref::<ApiError>().is_some_and(ApiError::is_auth_failure);
+    if error.is::<NotAuthenticated>() || api_auth_failure {
+        EXIT_NOT_AUTHENTICATED
+    } else if error.is::<AlreadyRunning>() {
+        EXIT_ALREADY_RUNNING
+    } else {
+        1
+    }
+}
+
+/// Rank exit codes, for when several profiles fail. Authentication problems come first, as they
+/// need someone to act, and another run holding the lock comes last, as it sorts itself out.
+fn exit_code_priority(code: u8) -> u8 {
+    match code {
+        0 => 0,
+        EXIT_ALREADY_RUNNING => 1,
+        EXIT_NOT_AUTHENTICATED => 3,
+        _ => 2,
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let configurations = match config_file::load() {
+        Ok(configurations) => configurations,
+        Err(error) => {
+            eprintln!("Error: {}", redact(&format!("{error:#}")));
+            return ExitCode::FAILURE;
         }
+    };
+    for config in &configurations {
+        config.register_secrets();
+    }
 
-        let response_text = request.text().await?;
+    let shutdown = match Shutdown::listen() {
+        Ok(shutdown) => shutdown,
+        Err(error) => {
+            eprintln!("Error: failed to listen for signals: {error}");
+            return ExitCode::FAILURE;
+        }
+    };
 
-        let response: CommentEditResponse = match serde_json::from_str(&response_text) {
-            Ok(response) => response,
-            Err(err) => {
-                eprintln!("Comment deletion parse failure: {err}");
-                eprintln!("Output failed to parse: {response_text}");
-                Err(err)?
+    if matches!(configurations[0].command, Some(Command::Daemon)) {
+        return match daemon::daemon(configurations, &shutdown).await {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(error) => {
+                eprintln!("Error: {}", redact(&format!("{error:#}")));
+                ExitCode::from(exit_code(&error))
             }
         };
+    }
+
+    // Profiles are processed one after the other; a failing profile doesn't stop the others
+    let mut code = 0;
+    for config in configurations {
+        if shutdown.requested() {
+            break;
+        }
 
-        return response.comment_view.comment.deleted.ok_or(anyhow!("Failed to verify deletion"));
+        if let Some(profile) = &config.profile_name {
+            println!("Processing profile {profile} ({})", config.canonical_username());
+        }
+
+        if let Err(error) = run(&config, &shutdown).await {
+            eprintln!("Error: {}", redact(&format!("{error:#}")));
+            code = std::cmp::max_by_key(code, exit_code(&error), |&code| exit_code_priority(code));
+        }
     }
 
+    ExitCode::from(code)
+}
+
+/// Run the action requested on the command line.
+async fn run(config: &Configuration, shutdown: &Shutdown) -> Result<()> {
+    let _lock = AccountLock::acquire(config, shutdown).await?;
+    let api = Api::new(config, shutdown)?;
 
-    Err(anyhow!("Too many failed tries, giving up on comment {}", comment.id))
+    match &config.command {
+        Some(Command::Restore(options)) => {
+            auth::authenticate(config, &api).await?;
+            restore::restore(config, &api, shutdown, options).await
+        }
+        Some(Command::Delete(options)) => {
+            auth::authenticate(config, &api).await?;
+            let state = State::open(config)?;
+            delete::delete(config, &api, &state, shutdown, options).await
+        }
+        Some(Command::Pin(options)) => pin(config, options, true),
+        Some(Command::Unpin(options)) => pin(config, options, false),
+        // Handled in main, as the daemon takes all profiles at once
+        Some(Command::Daemon) => unreachable!(),
+        None => {
+            let state = State::open(config)?;
+            cleanup(config, &api, &state, shutdown).await
+        }
+    }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let config = Configuration::parse();
+/// Add items to the pinned file, or remove them from it.
+fn pin(config: &Configuration, options: &PinOptions, pin: bool) -> Result<()> {
+    let mut pinned = Pinned::load(config)?;
+
+    if pin {
+        for item in &options.items {
+            pinned.check(item)?;
+        }
+    }
+
+    for item in &options.items {
+        if pin {
+            if pinned.pin(item) {
+                println!("Pinned {item}");
+            } else {
+                println!("{item} was already pinned");
+            }
+        } else if pinned.unpin(item) {
+            println!("Unpinned {item}");
+        } else {
+            println!("{item} was not pinned");
+        }
+    }
 
-    println!("Hello, {username}, after this program succeeds you should only have {duration} days of comments and posts left", username = config.canonical_username(), duration = config.days_to_keep);
+    pinned.save()?;
+    println!("Pinned items are kept in {}", pinned.path().display());
+
+    Ok(())
+}
+
+/// Delete the old posts and comments of a single account.
+pub(crate) async fn cleanup(config: &Configuration, api: &Api, state: &State, shutdown: &Shutdown) -> Result<()> {
+    let username = config.canonical_username();
+    match (config.after, config.before) {
+        (None, None) => println!("Hello, {username}, after this program succeeds you should only have {duration} days of comments and posts left", duration = config.days_to_keep),
+        (Some(after), None) => println!("Hello, {username}, this will delete comments and posts published since {after}"),
+        (None, Some(before)) => println!("Hello, {username}, this will delete comments and posts published before {before}"),
+        (Some(after), Some(before)) => println!("Hello, {username}, this will delete comments and posts published between {after} and {before}"),
+    }
     if config.keep_favourites {
         println!(" + Items you've favourited will also be kept");
     }
@@ -314,11 +513,70 @@ async fn main() -> Result<()> {
     if config.keep_downvotes {
         println!(" + Downvotes will also be kept");
     }
+    if let Some(minimum) = config.keep_min_score {
+        println!(" + Items with a score of {minimum} or more will also be kept");
+    }
+    if let Some(minimum) = config.keep_min_replies {
+        println!(" + Items with {minimum} or more replies will also be kept");
+    }
+    for pattern in &config.keep_patterns {
+        println!(" + Items matching '{pattern}' will be kept");
+    }
+    for pattern in &config.delete_patterns {
+        println!(" + Items matching '{pattern}' will be deleted regardless of age");
+    }
+    if config.keep_newest_comments > 0 {
+        println!(" + Your newest {} comments will be kept", config.keep_newest_comments);
+    }
+    if config.keep_newest_posts > 0 {
+        println!(" + Your newest {} posts will be kept", config.keep_newest_posts);
+    }
+    if let Some(threshold) = config.low_score_threshold {
+        println!(" + Items with a score below {threshold} will be deleted after {} days", config.low_score_days);
+    }
+    for rule in &config.community_rules {
+        match rule.retention {
+            Retention::Days(days) => println!(" + Items in {} will be kept for {days} days", rule.community),
+            Retention::Never => println!(" + Items in {} will never be deleted", rule.community),
+            Retention::Immediately => println!(" + Items in {} will be deleted regardless of age", rule.community),
+        }
+    }
     if config.edit_then_delete {
         println!(" + Comments will first be edited into the string '{}'", config.edit_text);
     }
+    if config.dry_run {
+        println!(" + Dry run: nothing will actually be edited or deleted");
+    }
+
+    auth::authenticate(config, api).await?;
+
+    let (comments, posts) = gather_data_from_profile(config, api, state, shutdown).await?;
+
+    let pinned = Pinned::load(config)?;
+    let (comments, dropped_comments) = merge_leftovers(ItemKind::Comment, comments, state.journal_comments()?, &pinned, comment_key);
+    let (posts, dropped_posts) = merge_leftovers(ItemKind::Post, posts, state.journal_posts()?, &pinned, post_key);
+
+    if config.dry_run {
+        return print_deletion_plan(config, &comments, &posts);
+    }
+
+    for id in dropped_comments {
+        state.journal_mark(ItemKind::Comment, id, JournalStatus::Done)?;
+    }
+    for id in dropped_posts {
+        state.journal_mark(ItemKind::Post, id, JournalStatus::Done)?;
+    }
+
+    delete_items(config, api, state, shutdown, comments, posts).await
+}
 
-    let (comments, posts) = gather_data_from_profile(&config).await?;
+/// Archive, edit and delete the given posts and comments, keeping track of every step in the
+/// journal and the state database.
+///
+/// Only the given items are handled. Other items in the journal are left for the run that
+/// planned them, or for the next cleanup run to check again.
+pub(crate) async fn delete_items(config: &Configuration, api: &Api, state: &State, shutdown: &Shutdown, comments: Vec<Planned<CommentView>>, posts: Vec<Planned<PostView>>) -> Result<()> {
+    state.journal_plan(&comments, &posts)?;
 
     let post_count = posts.len();
     let comment_count = comments.len();
@@ -329,36 +587,118 @@ async fn main() -> Result<()> {
     let mut comment_delete_failed = 0;
     let mut comment_delete_unverified = 0;
 
-    for post in posts {
-        match delete_post(&config, &post).await {
+    let mut archive = if post_count + comment_count > 0 {
+        let archive = Archive::create(config)?;
+        println!("Original posts and comments will be archived to {}", archive.path().display());
+        Some(archive)
+    } else {
+        None
+    };
+
+    for (post_view, _status) in posts {
+        if shutdown.requested() {
+            break;
+        }
+
+        let post = &post_view.post;
+
+        if let Some(archive) = &mut archive {
+            if let Err(error) = archive.store(&ArchivedItem::from(&post_view)) {
+                post_delete_failed += 1;
+                eprintln!("Not deleting post {}, archiving it failed: {error}", post.item_id());
+                continue;
+            }
+        }
+
+        match delete_post(config, api, post).await {
             Ok(delete_respected) => {
                 println!("Delete for post{} respected: {post}", if delete_respected { "" } else { " NOT" });
                 if !delete_respected {
                     post_delete_unverified += 1;
                 }
+                let outcome = if delete_respected { Outcome::Deleted } else { Outcome::DeleteUnverified };
+                state.record(ItemKind::Post, post.id, outcome, None)?;
             }
+            Err(error) if is_interrupted(&error) => break,
             Err(error) => {
                 post_delete_failed += 1;
                 eprintln!("Deletion request failed for post {}: {error}", post.item_id());
+                state.record(ItemKind::Post, post.id, Outcome::DeleteFailed, Some(&error.to_string()))?;
+                if is_fatal(&error) {
+                    return Err(error.context("Stopping the run, the item is left in the journal for the next run"));
+                }
             }
         }
+        state.journal_mark(ItemKind::Post, post.id, JournalStatus::Done)?;
     }
 
-    for comment in comments {
-        match delete_comment(&config, &comment).await {
+    for (comment_view, status) in comments {
+        if shutdown.requested() {
+            break;
+        }
+
+        let comment = &comment_view.comment;
+
+        // Edited comments have been archived before their edit, in the run that was interrupted. A
+        // comment that already holds the edit text was edited by an earlier run whose delete failed,
+        // archiving it again would bury the original text under the edit text.
+        let already_edited = comment.content == config.encoded_edit_text();
+        if status == JournalStatus::Pending && !already_edited {
+            if let Some(archive) = &mut archive {
+                if let Err(error) = archive.store(&ArchivedItem::from(&comment_view)) {
+                    comment_delete_failed += 1;
+                    eprintln!("Not deleting comment {}, archiving it failed: {error}", comment.item_id());
+                    continue;
+                }
+            }
+
+            if config.edit_then_delete {
+                if let Err(error) = edit_comment(config, api, comment).await {
+                    if is_interrupted(&error) {
+                        break;
+                    }
+                    comment_delete_failed += 1;
+                    eprintln!("Edit request failed for comment {}: {error}", comment.item_id());
+                    state.record(ItemKind::Comment, comment.id, Outcome::DeleteFailed, Some(&error.to_string()))?;
+                    if is_fatal(&error) {
+                        return Err(error.context("Stopping the run, the item is left in the journal for the next run"));
+                    }
+                    state.journal_mark(ItemKind::Comment, comment.id, JournalStatus::Done)?;
+                    continue;
+                }
+                state.journal_mark(ItemKind::Comment, comment.id, JournalStatus::Edited)?;
+            }
+        }
+
+        match delete_comment(config, api, comment).await {
             Ok(delete_respected) => {
                 println!("Delete for comment{} respected: {comment}", if delete_respected { "" } else { " NOT" });
                 if !delete_respected {
                     comment_delete_unverified += 1;
                 }
+                let outcome = if delete_respected { Outcome::Deleted } else { Outcome::DeleteUnverified };
+                state.record(ItemKind::Comment, comment.id, outcome, None)?;
             }
+            Err(error) if is_interrupted(&error) => break,
             Err(error) => {
                 comment_delete_failed += 1;
-                eprintln!("Deletion request failed for comment {}: {error}", comment.item_id())
+                eprintln!("Deletion request failed for comment {}: {error}", comment.item_id());
+                state.record(ItemKind::Comment, comment.id, Outcome::DeleteFailed, Some(&error.to_string()))?;
+                if is_fatal(&error) {
+                    return Err(error.context("Stopping the run, the item is left in the journal for the next run"));
+                }
             }
         }
+        state.journal_mark(ItemKind::Comment, comment.id, JournalStatus::Done)?;
     }
 
+    if shutdown.requested() {
+        let remaining = state.journal_comments()?.len() + state.journal_posts()?.len();
+        eprintln!("Stopped early, {remaining} items are left in the journal for the next run");
+        return Ok(());
+    }
+    state.journal_prune()?;
+
     if post_delete_failed != 0 || comment_delete_failed != 0 {
         eprintln!("Failed to delete {post_delete_failed}/{post_count} posts and {comment_delete_failed}/{comment_count} comments");
     } else {
@@ -367,3 +707,78 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{remove_file, write};
+    use chrono::{Days, Utc};
+    use clap::Parser;
+    use crate::archive::ItemKind;
+    use crate::configuration::Configuration;
+    use crate::fixtures::comment;
+    use crate::pinned::Pinned;
+    use crate::state::{JournalStatus, State};
+    use super::{comment_key, merge_leftovers};
+
+    /// The journal of a run that was interrupted after editing comment 1, before it got to comments 2 and 3.
+    fn interrupted_run() -> State {
+        let state = State::in_memory("me@lemmy.example").unwrap();
+        let old = Utc::now() - Days::new(100);
+        let planned: Vec<_> = (1..=3).map(|id| (comment(id, "Hello", 1, old), JournalStatus::Pending)).collect();
+
+        state.journal_plan(&planned, &[]).unwrap();
+        state.journal_mark(ItemKind::Comment, 1, JournalStatus::Edited).unwrap();
+        state
+    }
+
+    /// Load a pinned file holding the given lines. Every test needs its own name, as tests run in parallel.
+    fn pinned(name: &str, lines: &str) -> Pinned {
+        let path = std::env::temp_dir().join(format!("lemmy-old-comment-purge-{}-{name}.pinned", std::process::id()));
+        write(&path, lines).unwrap();
+
+        let config = Configuration::parse_from(["lemmy-old-comment-purge", "--username", "me@lemmy.example", "--pinned-file", path.to_str().unwrap()]);
+        let pinned = Pinned::load(&config).unwrap();
+        remove_file(&path).unwrap();
+        pinned
+    }
+
+    /// Merge the leftovers of an interrupted run with the comments selected now.
+    fn merge(selected: &[i64], pinned: &Pinned) -> (Vec<(i64, JournalStatus)>, Vec<i64>) {
+        let state = interrupted_run();
+        let selected = selected.iter().map(|&id| comment(id, "Hello", 1, Utc::now() - Days::new(100))).collect();
+
+        let (planned, dropped) = merge_leftovers(ItemKind::Comment, selected, state.journal_comments().unwrap(), pinned, comment_key);
+        (planned.iter().map(|(view, status)| (view.comment.id, *status)).collect(), dropped)
+    }
+
+    #[test]
+    fn edited_leftovers_are_finished_when_no_longer_selected() {
+        let (planned, _) = merge(&[], &pinned("edited", ""));
+
+        assert_eq!(planned, [(1, JournalStatus::Edited)]);
+    }
+
+    #[test]
+    fn reselected_leftovers_keep_their_progress() {
+        let (planned, dropped) = merge(&[3, 1, 4], &pinned("reselected", ""));
+
+        assert_eq!(planned, [(3, JournalStatus::Pending), (1, JournalStatus::Edited), (4, JournalStatus::Pending)]);
+        assert_eq!(dropped, [2]);
+    }
+
+    #[test]
+    fn pinned_leftovers_are_dropped() {
+        let (planned, dropped) = merge(&[], &pinned("pinned", "comment:1\nhttps://lemmy.example/comment/3\n"));
+
+        assert_eq!(planned, []);
+        assert_eq!(dropped, [1, 2, 3]);
+    }
+
+    #[test]
+    fn pending_leftovers_are_dropped_when_no_longer_selected() {
+        let (planned, dropped) = merge(&[], &pinned("pending", ""));
+
+        assert_eq!(planned, [(1, JournalStatus::Edited)]);
+        assert_eq!(dropped, [2, 3]);
+    }
+}
@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+
+/// An object representing a user account. Simplified.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct Person {
+    pub(crate) id: i64,
+    /// The username, without the instance.
+    pub(crate) name: String,
+    /// The federated ID of the user, i.e. https://lemmy.example/u/name
+    #[serde(alias = "ap_id")]
+    pub(crate) actor_id: String,
+}
+
+impl Person {
+    /// The username in the `name@instance` form used in the configuration.
+    pub fn qualified_name(&self) -> String {
+        format!("{}@{}", self.name, crate::helper::instance_of(&self.actor_id))
+    }
+}
@@ -0,0 +1,124 @@
+use std::fs::{create_dir_all, read_to_string, write};
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Context, Result};
+use crate::archive::ItemKind;
+use crate::configuration::Configuration;
+use crate::delete::Target;
+
+/// Check whether two targets point to the same item, i.e. `comment:12` and a link to it on the home instance.
+fn same(home: &str, a: &Target, b: &Target) -> bool {
+    a == b || a.local_id(home).is_some_and(|local| b.local_id(home) == Some(local))
+}
+
+/// The posts and comments that must never be deleted, whatever the rules say.
+///
+/// The list is a text file with one item per line, as a URL or as `comment:ID` and `post:ID`, just
+/// like the items passed to the `delete` subcommand. Empty lines and lines starting with `#` are
+/// ignored, so the file can be edited by hand as well as with `pin` and `unpin`.
+pub(crate) struct Pinned {
+    path: PathBuf,
+    /// The instance of the user, which the IDs in the file refer to.
+    home: String,
+    /// Every line of the file, to write back the comments along with the items.
+    lines: Vec<String>,
+    items: Vec<Target>,
+}
+
+impl Pinned {
+    /// The pinned file of the configured user: either the one configured, or one named after the user
+    /// in the configuration directory.
+    fn locate(config: &Configuration) -> Result<PathBuf> {
+        if let Some(path) = &config.pinned_file {
+            return Ok(path.clone());
+        }
+
+        let directory = dirs::config_dir()
+            .ok_or(anyhow!("Can't find the configuration directory, set the pinned file explicitly"))?;
+        Ok(directory.join("distenourize").join(format!("{}.pinned", config.canonical_username())))
+    }
+
+    /// Read the pinned items of the configured user. A missing file holds no items.
+    pub fn load(config: &Configuration) -> Result<Self> {
+        let path = Self::locate(config)?;
+        let home = config.instance().ok_or(anyhow!("Invalid username"))?.to_string();
+
+        let contents = match read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error).with_context(|| format!("Failed to read pinned items from {}", path.display())),
+        };
+
+        let lines: Vec<String> = contents.lines().map(str::to_string).collect();
+        let items = lines.iter()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.parse().with_context(|| format!("Invalid pinned item in {}", path.display())))
+            .collect::<Result<_>>()?;
+
+        for item in &items {
+            if !Self::can_match(&home, item) {
+                eprintln!("Pinned item {item} in {} is not on {home} and protects nothing, pin it as comment:ID or post:ID", path.display());
+            }
+        }
+
+        Ok(Self { path, home, lines, items })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Check whether an item has been pinned, by ID or by URL.
+    pub fn contains(&self, kind: ItemKind, id: i64, ap_id: &str) -> bool {
+        self.items.iter().any(|item| item.refers_to(&self.home, kind, id, ap_id))
+    }
+
+    /// Check whether a pinned item can ever match one of the user's posts and comments. Those live
+    /// on the home instance, so links to other instances never do.
+    fn can_match(home: &str, item: &Target) -> bool {
+        item.local_id(home).is_some()
+    }
+
+    /// Check that an item can be pinned, before anything is added to the list.
+    pub fn check(&self, target: &Target) -> Result<()> {
+        if !Self::can_match(&self.home, target) {
+            return Err(anyhow!("Can't pin {target}, it is not a post or comment link on {}. Pin it as comment:ID or post:ID, with the ID on {0}", self.home));
+        }
+        Ok(())
+    }
+
+    /// Add an item to the list. Returns false if it was already pinned.
+    pub fn pin(&mut self, target: &Target) -> bool {
+        if self.items.iter().any(|item| same(&self.home, item, target)) {
+            return false;
+        }
+
+        self.lines.push(target.to_string());
+        self.items.push(target.clone());
+        true
+    }
+
+    /// Remove an item from the list. Returns false if it wasn't pinned.
+    pub fn unpin(&mut self, target: &Target) -> bool {
+        let before = self.items.len();
+
+        let home = &self.home;
+        self.lines.retain(|line| !line.trim().parse().is_ok_and(|item| same(home, &item, target)));
+        self.items.retain(|item| !same(home, item, target));
+
+        self.items.len() != before
+    }
+
+    /// Write the list back to its file.
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+        }
+
+        let mut contents = self.lines.join("\n");
+        contents.push('\n');
+        write(&self.path, contents)
+            .with_context(|| format!("Failed to write pinned items to {}", self.path.display()))
+    }
+}
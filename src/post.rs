@@ -1,16 +1,24 @@
 use std::fmt::{Display, Formatter};
 use chrono::{DateTime, Utc};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 /// An object representing a single post. Simplified.
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub(crate) struct Post {
     pub(crate) id: i64,
+    /// The federated ID of the post, usually its URL on the home instance.
+    pub(crate) ap_id: String,
     pub(crate) name: String,
+    /// The text of the post, if any. Markdown.
+    pub(crate) body: Option<String>,
+    /// The link the post points to, if any.
+    pub(crate) url: Option<String>,
     pub(crate) removed: bool,
     pub(crate) deleted: bool,
-    #[serde(deserialize_with = "crate::helper::deserialize_date")]
+    #[serde(alias = "published_at", deserialize_with = "crate::helper::deserialize_date")]
     pub(crate) published: DateTime<Utc>,
+    #[serde(default, alias = "updated_at", deserialize_with = "crate::helper::deserialize_optional_date")]
+    pub(crate) updated: Option<DateTime<Utc>>,
 }
 
 impl Post {
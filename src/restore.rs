@@ -0,0 +1,162 @@
+use std::collections::HashSet;
+use anyhow::{anyhow, Context, Result};
+use crate::api::{is_fatal, is_interrupted, Api};
+use crate::archive::{Archive, ArchivedItem, ItemKind};
+use crate::configuration::{Configuration, RestoreOptions};
+use crate::shutdown::Shutdown;
+use crate::state::{Outcome, State};
+
+/// Check whether an archived item was selected by the restore options.
+fn is_selected(options: &RestoreOptions, home: &str, item: &ArchivedItem) -> bool {
+    if !options.ids.is_empty() && !options.ids.iter().any(|target| target.refers_to(home, item.kind, item.id, &item.ap_id)) {
+        return false;
+    }
+
+    if options.since.is_some_and(|since| item.archived_at < since) {
+        return false;
+    }
+
+    if options.until.is_some_and(|until| item.archived_at >= until) {
+        return false;
+    }
+
+    true
+}
+
+/// Undelete a comment and put its original contents back.
+async fn restore_comment(config: &Configuration, api: &Api, item: &ArchivedItem) -> Result<()> {
+    let view = api.delete_comment(config, item.id, false)
+        .await
+        .context("Undelete failed")?;
+    if view.comment.removed {
+        return Err(anyhow!("Comment has been removed by a moderator"));
+    }
+    if view.comment.deleted == Some(true) {
+        return Err(anyhow!("Server did not undelete the comment"));
+    }
+
+    let Some(content) = &item.content else {
+        return Ok(());
+    };
+
+    config.wait().await;
+
+    let view = api.edit_comment(config, item.id, content)
+        .await
+        .context("Undeleted, but putting the original text back failed")?;
+    if &view.comment.content != content {
+        return Err(anyhow!("Undeleted, but the server did not accept the original text"));
+    }
+
+    Ok(())
+}
+
+/// Undelete a post. Posts are never edited before deletion, so there is no content to put back.
+async fn restore_post(config: &Configuration, api: &Api, item: &ArchivedItem) -> Result<()> {
+    let view = api.delete_post(config, item.id, false)
+        .await
+        .context("Undelete failed")?;
+    if view.post.removed {
+        return Err(anyhow!("Post has been removed by a moderator"));
+    }
+    if view.post.deleted {
+        return Err(anyhow!("Server did not undelete the post"));
+    }
+
+    Ok(())
+}
+
+/// Restore the items from one or more run archives.
+///
+/// Every selected item is undeleted, and comments get their original contents back. Items that
+/// cannot be restored are reported at the end; they do not stop the other items from being restored.
+/// In a dry run, the items are only listed.
+pub(crate) async fn restore(config: &Configuration, api: &Api, shutdown: &Shutdown, options: &RestoreOptions) -> Result<()> {
+    let archives = if options.archives.is_empty() {
+        Archive::find_all(config)?
+    } else {
+        options.archives.clone()
+    };
+
+    let home = config.instance().ok_or(anyhow!("Invalid username"))?;
+
+    let mut items = vec![];
+    for path in &archives {
+        println!("Reading archive {}", path.display());
+        items.extend(Archive::read(path)?.into_iter().filter(|item| is_selected(options, home, item)));
+    }
+
+    // An item can be archived again by a later run, after a failed delete left it edited. Only the
+    // earliest copy holds the original text.
+    items.sort_by_key(|item| item.archived_at);
+    let mut seen = HashSet::new();
+    items.retain(|item| seen.insert((item.kind, item.id)));
+
+    for target in &options.ids {
+        if !items.iter().any(|item| target.refers_to(home, item.kind, item.id, &item.ap_id)) {
+            eprintln!("Item {target} is not in any of the archives, can't restore it");
+        }
+    }
+
+    if config.dry_run {
+        println!("Dry run, nothing will be changed");
+        for item in &items {
+            println!("Would restore: {} {} ({})", item.kind.as_str(), item.id, item.ap_id);
+        }
+        println!("Would restore {} items", items.len());
+        return Ok(());
+    }
+
+    println!("Will try to restore {} items", items.len());
+
+    let state = State::open(config)?;
+    // Restored items reappear on pages the next crawl would otherwise skip
+    state.set_start_page(1, false)?;
+
+    let mut failures = vec![];
+    let mut restored = 0;
+    for item in &items {
+        if shutdown.requested() {
+            break;
+        }
+
+        config.wait().await;
+
+        let result = match item.kind {
+            ItemKind::Comment => restore_comment(config, api, item).await,
+            ItemKind::Post => restore_post(config, api, item).await,
+        };
+
+        match result {
+            Ok(()) => {
+                println!("Restored {} {} ({})", item.kind.as_str(), item.id, item.ap_id);
+                restored += 1;
+                state.record(item.kind, item.id, Outcome::Restored, None)?;
+            }
+            Err(error) if is_interrupted(&error) => break,
+            Err(error) => {
+                eprintln!("Failed to restore {} {}: {error}", item.kind.as_str(), item.id);
+                state.record(item.kind, item.id, Outcome::RestoreFailed, Some(&error.to_string()))?;
+                if is_fatal(&error) {
+                    return Err(error.context("Stopping the restore"));
+                }
+                failures.push((item, error));
+            }
+        }
+    }
+
+    if shutdown.requested() {
+        eprintln!("Stopped early, restored {restored}/{} items", items.len());
+    } else if failures.is_empty() {
+        println!("All {} items were restored", items.len());
+    }
+
+    if !failures.is_empty() {
+        eprintln!("Failed to restore {}/{} items:", failures.len(), items.len());
+        for (item, error) in failures {
+            eprintln!(" - {} {} ({}): {error}", item.kind.as_str(), item.id, item.ap_id);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,199 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use anyhow::{anyhow, Error};
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use crate::community::Community;
+
+/// How long items are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Retention {
+    /// Keep items for this many days.
+    Days(u64),
+    /// Never delete items.
+    Never,
+    /// Delete items as soon as they are seen, regardless of age.
+    Immediately,
+}
+
+impl FromStr for Retention {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "never" | "forever" => Ok(Retention::Never),
+            "now" | "immediately" => Ok(Retention::Immediately),
+            days => days.parse()
+                .map(Retention::Days)
+                .map_err(|_| anyhow!("Invalid retention '{value}', expected a number of days, 'never' or 'now'")),
+        }
+    }
+}
+
+impl Display for Retention {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Retention::Days(days) => write!(f, "{days} days"),
+            Retention::Never => write!(f, "never"),
+            Retention::Immediately => write!(f, "now"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Retention {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        /// Retention in a configuration file is either a number of days or one of the keywords.
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Value {
+            Days(u64),
+            Keyword(String),
+        }
+
+        match Value::deserialize(deserializer)? {
+            Value::Days(days) => Ok(Retention::Days(days)),
+            Value::Keyword(keyword) => keyword.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// The way a rule refers to a community.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum CommunitySelector {
+    /// The community ID on the user's home instance.
+    Id(i64),
+    /// The community name in `name@instance` form, lowercase.
+    Name(String),
+}
+
+impl FromStr for CommunitySelector {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim().trim_start_matches('!');
+
+        if let Ok(id) = value.parse() {
+            return Ok(CommunitySelector::Id(id));
+        }
+        if !value.contains('@') {
+            return Err(anyhow!("Invalid community '{value}', expected a community ID or name@instance"));
+        }
+
+        Ok(CommunitySelector::Name(value.to_lowercase()))
+    }
+}
+
+impl Display for CommunitySelector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommunitySelector::Id(id) => write!(f, "community {id}"),
+            CommunitySelector::Name(name) => write!(f, "!{name}"),
+        }
+    }
+}
+
+impl CommunitySelector {
+    pub fn matches(&self, community: &Community) -> bool {
+        match self {
+            CommunitySelector::Id(id) => community.id == *id,
+            CommunitySelector::Name(name) => community.qualified_name().eq_ignore_ascii_case(name),
+        }
+    }
+}
+
+/// A retention rule for a single community, overriding the global amount of days to keep.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CommunityRule {
+    pub(crate) community: CommunitySelector,
+    pub(crate) retention: Retention,
+}
+
+impl FromStr for CommunityRule {
+    type Err = Error;
+
+    /// Parse a rule in the `community=retention` form, i.e. `memes@lemmy.example=2`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (community, retention) = value.rsplit_once('=')
+            .ok_or(anyhow!("Invalid community rule '{value}', expected community=retention"))?;
+
+        Ok(Self {
+            community: community.parse()?,
+            retention: retention.parse()?,
+        })
+    }
+}
+
+impl Display for CommunityRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.community, self.retention)
+    }
+}
+
+/// A regular expression matched against the text of posts and comments.
+#[derive(Debug, Clone)]
+pub(crate) struct Pattern(Regex);
+
+impl Pattern {
+    /// Find the first match of the pattern in any of the texts.
+    pub fn find<'a>(&self, texts: &[&'a str]) -> Option<&'a str> {
+        texts.iter().find_map(|text| self.0.find(text)).map(|found| found.as_str())
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Regex::new(value)
+            .map(Pattern)
+            .map_err(|error| anyhow!("Invalid pattern '{value}': {error}"))
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.as_str())
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for Pattern {}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CommunityRule, CommunitySelector, Retention};
+
+    fn rule(value: &str) -> CommunityRule {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_community_rules() {
+        assert_eq!(rule("!Memes@Lemmy.Example=2"), CommunityRule {
+            community: CommunitySelector::Name("memes@lemmy.example".to_string()),
+            retention: Retention::Days(2),
+        });
+        assert_eq!(rule("42=never").community, CommunitySelector::Id(42));
+        assert_eq!(rule("42=Forever").retention, Retention::Never);
+        assert_eq!(rule("42=now").retention, Retention::Immediately);
+        assert_eq!(rule("42= immediately ").retention, Retention::Immediately);
+    }
+
+    #[test]
+    fn rejects_bad_community_rules() {
+        for value in ["memes@lemmy.example", "memes=2", "memes@lemmy.example=soon", "memes@lemmy.example=-1", "=2"] {
+            assert!(value.parse::<CommunityRule>().is_err(), "{value} should not parse");
+        }
+    }
+}
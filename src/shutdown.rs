@@ -0,0 +1,52 @@
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+/// A flag that is raised when the process is asked to stop (SIGINT or SIGTERM).
+///
+/// Long loops check this flag between items, so that the request in flight can finish and the
+/// journal stays consistent, instead of being cut off halfway.
+#[derive(Clone)]
+pub(crate) struct Shutdown {
+    requested: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    /// Start listening for SIGINT and SIGTERM in the background.
+    pub fn listen() -> std::io::Result<Self> {
+        let (sender, requested) = watch::channel(false);
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = terminate.recv() => {}
+                _ = interrupt.recv() => {}
+            }
+
+            eprintln!("Shutdown requested, finishing the current request before stopping");
+            sender.send_replace(true);
+
+            // A second signal means the user really wants the process gone
+            tokio::select! {
+                _ = terminate.recv() => {}
+                _ = interrupt.recv() => {}
+            }
+            eprintln!("Stopping immediately");
+            std::process::exit(130);
+        });
+
+        Ok(Self { requested })
+    }
+
+    /// Check whether the process has been asked to stop.
+    pub fn requested(&self) -> bool {
+        *self.requested.borrow()
+    }
+
+    /// Wait until the process is asked to stop.
+    pub async fn wait(&self) {
+        // The sender lives as long as the process, so this can only fail if it is already gone
+        let _ = self.requested.clone().wait_for(|&requested| requested).await;
+    }
+}
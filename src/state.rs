@@ -0,0 +1,310 @@
+use std::fs::create_dir_all;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::de::DeserializeOwned;
+use crate::api::{CommentView, PostView};
+use crate::archive::ItemKind;
+use crate::configuration::Configuration;
+use crate::within_days;
+
+/// How many days a run may skip pages before the whole profile is crawled again, to find items
+/// that moved onto skipped pages.
+const FULL_CRAWL_DAYS: u64 = 7;
+
+/// What happened to an item the last time this tool touched it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    /// The server confirmed the item was deleted.
+    Deleted,
+    /// The delete was requested, but the server did not confirm it.
+    DeleteUnverified,
+    /// The delete failed.
+    DeleteFailed,
+    /// The item was restored from an archive.
+    Restored,
+    /// Restoring the item from an archive failed.
+    RestoreFailed,
+}
+
+impl Outcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Deleted => "deleted",
+            Outcome::DeleteUnverified => "delete_unverified",
+            Outcome::DeleteFailed => "delete_failed",
+            Outcome::Restored => "restored",
+            Outcome::RestoreFailed => "restore_failed",
+        }
+    }
+}
+
+/// Where a planned item is in the edit and delete process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum JournalStatus {
+    /// Nothing has been done to the item yet.
+    Pending,
+    /// The comment has been overwritten, but not deleted yet.
+    Edited,
+    /// The item has been handled, successfully or not.
+    Done,
+}
+
+impl JournalStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            JournalStatus::Pending => "pending",
+            JournalStatus::Edited => "edited",
+            JournalStatus::Done => "done",
+        }
+    }
+}
+
+/// A local SQLite database remembering what previous runs did.
+///
+/// The `items` table holds the latest outcome per item, the `history` table holds every outcome
+/// ever recorded, and the `crawl` table remembers which profile page the next run can start at and
+/// when the whole profile was last crawled.
+/// The `journal` table holds the plan of the current run and how far along it is, so that an
+/// interrupted run can be picked up where it stopped.
+pub(crate) struct State {
+    connection: Connection,
+    username: String,
+}
+
+impl State {
+    /// Open (and if necessary create) the state database in the configured state directory.
+    pub fn open(config: &Configuration) -> Result<Self> {
+        create_dir_all(&config.state_dir)
+            .with_context(|| format!("Failed to create state directory {}", config.state_dir.display()))?;
+
+        let path = config.state_dir.join("state.sqlite");
+        let connection = Connection::open(&path)
+            .with_context(|| format!("Failed to open state database {}", path.display()))?;
+
+        Self::with_connection(connection, config.canonical_username())
+    }
+
+    /// A state database that only lives as long as the returned value.
+    #[cfg(test)]
+    pub fn in_memory(username: &str) -> Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?, username)
+    }
+
+    /// Create the tables that don't exist yet in a freshly opened database.
+    fn with_connection(connection: Connection, username: &str) -> Result<Self> {
+        connection.execute_batch("
+            CREATE TABLE IF NOT EXISTS items (
+                username TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                id INTEGER NOT NULL,
+                outcome TEXT NOT NULL,
+                updated_at TEXT NOT NULL,
+                PRIMARY KEY (username, kind, id)
+            );
+            CREATE TABLE IF NOT EXISTS history (
+                username TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                id INTEGER NOT NULL,
+                outcome TEXT NOT NULL,
+                detail TEXT,
+                at TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS crawl (
+                username TEXT PRIMARY KEY NOT NULL,
+                start_page INTEGER NOT NULL,
+                updated_at TEXT NOT NULL,
+                full_crawl_at TEXT
+            );
+            CREATE TABLE IF NOT EXISTS journal (
+                username TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                id INTEGER NOT NULL,
+                status TEXT NOT NULL,
+                item TEXT NOT NULL,
+                PRIMARY KEY (username, kind, id)
+            );
+        ")?;
+
+        // Databases created before full crawls were tracked lack the column
+        let tracks_full_crawls: bool = connection.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('crawl') WHERE name = 'full_crawl_at'",
+            [],
+            |row| row.get(0),
+        )?;
+        if !tracks_full_crawls {
+            connection.execute("ALTER TABLE crawl ADD COLUMN full_crawl_at TEXT", [])?;
+        }
+
+        Ok(Self {
+            connection,
+            username: username.to_string(),
+        })
+    }
+
+    /// The profile page the crawl can start at. Every page before it only held deleted items.
+    pub fn start_page(&self) -> Result<i64> {
+        let page = self.connection
+            .query_row("SELECT start_page FROM crawl WHERE username = ?1", params![self.username], |row| row.get(0))
+            .optional()?;
+
+        Ok(page.unwrap_or(1))
+    }
+
+    /// Check whether the whole profile has to be crawled, because it hasn't been in a while.
+    pub fn full_crawl_due(&self) -> Result<bool> {
+        let crawled_at: Option<Option<String>> = self.connection
+            .query_row("SELECT full_crawl_at FROM crawl WHERE username = ?1", params![self.username], |row| row.get(0))
+            .optional()?;
+
+        let crawled_at = crawled_at.flatten().and_then(|at| DateTime::parse_from_rfc3339(&at).ok());
+        Ok(!crawled_at.is_some_and(|at| within_days(at.with_timezone(&Utc), FULL_CRAWL_DAYS)))
+    }
+
+    /// Remember the profile page the next crawl can start at, and whether this crawl covered the
+    /// whole profile.
+    pub fn set_start_page(&self, page: i64, full_crawl: bool) -> Result<()> {
+        let now = Utc::now().to_rfc3339();
+        let full_crawl_at = full_crawl.then_some(&now);
+
+        self.connection.execute(
+            "INSERT INTO crawl (username, start_page, updated_at, full_crawl_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (username) DO UPDATE SET start_page = excluded.start_page, updated_at = excluded.updated_at,
+                 full_crawl_at = COALESCE(excluded.full_crawl_at, crawl.full_crawl_at)",
+            params![self.username, page, now, full_crawl_at],
+        )?;
+
+        Ok(())
+    }
+
+    /// Check if an earlier run deleted this item and the server confirmed it.
+    pub fn is_deleted(&self, kind: ItemKind, id: i64) -> Result<bool> {
+        let outcome: Option<String> = self.connection
+            .query_row(
+                "SELECT outcome FROM items WHERE username = ?1 AND kind = ?2 AND id = ?3",
+                params![self.username, kind.as_str(), id],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(outcome.as_deref() == Some(Outcome::Deleted.as_str()))
+    }
+
+    /// Record what happened to an item, both as its latest outcome and in the history.
+    pub fn record(&self, kind: ItemKind, id: i64, outcome: Outcome, detail: Option<&str>) -> Result<()> {
+        let now = Utc::now().to_rfc3339();
+
+        self.connection.execute(
+            "INSERT INTO items (username, kind, id, outcome, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (username, kind, id) DO UPDATE SET outcome = excluded.outcome, updated_at = excluded.updated_at",
+            params![self.username, kind.as_str(), id, outcome.as_str(), now],
+        )?;
+        self.connection.execute(
+            "INSERT INTO history (username, kind, id, outcome, detail, at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![self.username, kind.as_str(), id, outcome.as_str(), detail, now],
+        )?;
+
+        Ok(())
+    }
+
+    /// Add the items a run is about to process to the journal.
+    ///
+    /// Items already in the journal, left over from an interrupted run, keep their progress.
+    /// Items the interrupted run already finished with are planned again.
+    pub fn journal_plan(&self, comments: &[(CommentView, JournalStatus)], posts: &[(PostView, JournalStatus)]) -> Result<()> {
+        let transaction = self.connection.unchecked_transaction()?;
+        {
+            let mut insert = transaction.prepare(
+                "INSERT INTO journal (username, kind, id, status, item) VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT (username, kind, id) DO UPDATE SET status = excluded.status, item = excluded.item
+                 WHERE status = 'done'"
+            )?;
+
+            for (comment, _status) in comments {
+                let item = serde_json::to_string(comment)?;
+                insert.execute(params![self.username, ItemKind::Comment.as_str(), comment.comment.id, JournalStatus::Pending.as_str(), item])?;
+            }
+            for (post, _status) in posts {
+                let item = serde_json::to_string(post)?;
+                insert.execute(params![self.username, ItemKind::Post.as_str(), post.post.id, JournalStatus::Pending.as_str(), item])?;
+            }
+        }
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    /// Update how far along an item in the journal is.
+    pub fn journal_mark(&self, kind: ItemKind, id: i64, status: JournalStatus) -> Result<()> {
+        self.connection.execute(
+            "UPDATE journal SET status = ?4 WHERE username = ?1 AND kind = ?2 AND id = ?3",
+            params![self.username, kind.as_str(), id, status.as_str()],
+        )?;
+
+        Ok(())
+    }
+
+    /// All comments in the journal that haven't been handled yet, with their progress.
+    pub fn journal_comments(&self) -> Result<Vec<(CommentView, JournalStatus)>> {
+        self.journal_items(ItemKind::Comment)
+    }
+
+    /// All posts in the journal that haven't been handled yet, with their progress.
+    pub fn journal_posts(&self) -> Result<Vec<(PostView, JournalStatus)>> {
+        self.journal_items(ItemKind::Post)
+    }
+
+    fn journal_items<T: DeserializeOwned>(&self, kind: ItemKind) -> Result<Vec<(T, JournalStatus)>> {
+        let mut select = self.connection.prepare(
+            "SELECT item, status FROM journal WHERE username = ?1 AND kind = ?2 AND status != ?3 ORDER BY rowid"
+        )?;
+        let rows = select.query_map(params![self.username, kind.as_str(), JournalStatus::Done.as_str()], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut items = vec![];
+        for row in rows {
+            let (item, status) = row?;
+            let status = if status == JournalStatus::Edited.as_str() { JournalStatus::Edited } else { JournalStatus::Pending };
+            items.push((serde_json::from_str(&item)?, status));
+        }
+
+        Ok(items)
+    }
+
+    /// Remove the items that have been handled from the journal, once a run has finished.
+    pub fn journal_prune(&self) -> Result<()> {
+        self.connection.execute(
+            "DELETE FROM journal WHERE username = ?1 AND status = ?2",
+            params![self.username, JournalStatus::Done.as_str()],
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use crate::archive::ItemKind;
+    use crate::fixtures::comment;
+    use super::{JournalStatus, State};
+
+    fn progress(state: &State) -> Vec<(i64, JournalStatus)> {
+        state.journal_comments().unwrap().iter().map(|(view, status)| (view.comment.id, *status)).collect()
+    }
+
+    #[test]
+    fn planning_again_keeps_the_progress_of_leftovers() {
+        let state = State::in_memory("me@lemmy.example").unwrap();
+        let planned: Vec<_> = (1..=3).map(|id| (comment(id, "Hello", 1, Utc::now()), JournalStatus::Pending)).collect();
+
+        state.journal_plan(&planned, &[]).unwrap();
+        state.journal_mark(ItemKind::Comment, 1, JournalStatus::Edited).unwrap();
+        state.journal_mark(ItemKind::Comment, 2, JournalStatus::Done).unwrap();
+        assert_eq!(progress(&state), [(1, JournalStatus::Edited), (3, JournalStatus::Pending)]);
+
+        state.journal_plan(&planned, &[]).unwrap();
+        assert_eq!(progress(&state), [(1, JournalStatus::Edited), (2, JournalStatus::Pending), (3, JournalStatus::Pending)]);
+    }
+}
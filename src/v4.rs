@@ -0,0 +1,166 @@
+//! The response shapes of Lemmy's v4 API (Lemmy 1.0 and later), and their conversion into the
+//! shapes of the v3 API that the rest of the tool works with.
+//!
+//! Compared to v3, the aggregated counts are part of the post and comment themselves, the votes
+//! and saves of the user are grouped into `*_actions`, federated IDs of people and communities are
+//! called `ap_id`, timestamps end in `_at`, and the content of a profile is a single list of posts
+//! and comments that is paged with a cursor.
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use crate::api::{self, Unreadable};
+use crate::archive::ItemKind;
+use crate::comment::Comment;
+use crate::community::Community;
+use crate::person::Person;
+use crate::post::Post;
+
+/// A comment, along with the counts that v3 kept apart. Simplified.
+#[derive(Deserialize)]
+pub(crate) struct CommentWithCounts {
+    #[serde(flatten)]
+    comment: Comment,
+    score: i64,
+    child_count: i64,
+}
+
+/// A post, along with the counts that v3 kept apart. Simplified.
+#[derive(Deserialize)]
+pub(crate) struct PostWithCounts {
+    #[serde(flatten)]
+    post: Post,
+    score: i64,
+    comments: i64,
+}
+
+/// What the user did with a post or comment. Simplified.
+#[derive(Deserialize)]
+pub(crate) struct Actions {
+    /// The vote of the user (1 or -1), if they voted.
+    like_score: Option<i64>,
+    /// When the user saved the item, if they did.
+    #[serde(default, deserialize_with = "crate::helper::deserialize_optional_date")]
+    saved_at: Option<DateTime<Utc>>,
+}
+
+/// A comment with its context. Simplified.
+#[derive(Deserialize)]
+pub(crate) struct CommentView {
+    comment: CommentWithCounts,
+    creator: Person,
+    community: Community,
+    comment_actions: Option<Actions>,
+}
+
+impl From<CommentView> for api::CommentView {
+    fn from(view: CommentView) -> Self {
+        let actions = view.comment_actions;
+
+        Self {
+            comment: view.comment.comment,
+            creator: view.creator,
+            community: view.community,
+            counts: api::CommentAggregates {
+                score: view.comment.score,
+                child_count: view.comment.child_count,
+            },
+            saved: actions.as_ref().is_some_and(|actions| actions.saved_at.is_some()),
+            my_vote: actions.and_then(|actions| actions.like_score),
+        }
+    }
+}
+
+/// A post with its context. Simplified.
+#[derive(Deserialize)]
+pub(crate) struct PostView {
+    post: PostWithCounts,
+    creator: Person,
+    community: Community,
+    post_actions: Option<Actions>,
+}
+
+impl From<PostView> for api::PostView {
+    fn from(view: PostView) -> Self {
+        let actions = view.post_actions;
+
+        Self {
+            deleted: Some(view.post.post.deleted),
+            post: view.post.post,
+            creator: view.creator,
+            community: view.community,
+            counts: api::PostAggregates {
+                score: view.post.score,
+                comments: view.post.comments,
+            },
+            saved: actions.as_ref().is_some_and(|actions| actions.saved_at.is_some()),
+            my_vote: actions.and_then(|actions| actions.like_score),
+        }
+    }
+}
+
+/// An item in a list that mixes posts and comments. Simplified.
+#[derive(Deserialize)]
+#[serde(tag = "type_")]
+pub(crate) enum ContentView {
+    Comment(CommentView),
+    Post(PostView),
+    /// People and communities, which can show up when resolving a URL.
+    #[serde(other)]
+    Other,
+}
+
+/// A struct representing the response to an API call that returns a comment. Simplified.
+#[derive(Deserialize)]
+pub(crate) struct CommentResponse {
+    pub(crate) comment_view: CommentView,
+}
+
+/// A struct representing the response to an API call that returns a post. Simplified.
+#[derive(Deserialize)]
+pub(crate) struct PostResponse {
+    pub(crate) post_view: PostView,
+}
+
+/// A struct representing the response to a resolve object API call. Simplified.
+#[derive(Deserialize)]
+pub(crate) struct ResolveObjectResponse {
+    pub(crate) resolve: Option<ContentView>,
+}
+
+/// A struct representing a page of the posts and comments of a person. Simplified.
+#[derive(Deserialize)]
+pub(crate) struct PersonContentResponse {
+    /// The posts and comments, which are read one by one so that a bad item doesn't spoil the page.
+    pub(crate) items: Vec<Value>,
+    /// The cursor for the next page. Missing on the last page.
+    pub(crate) next_page: Option<String>,
+}
+
+impl From<PersonContentResponse> for api::ProfilePage {
+    fn from(response: PersonContentResponse) -> Self {
+        let mut page = api::ProfilePage {
+            comments: vec![],
+            posts: vec![],
+            unreadable: vec![],
+            next_page: response.next_page,
+        };
+
+        for value in response.items {
+            let kind = match value["type_"].as_str() {
+                Some("Comment") => ItemKind::Comment,
+                Some("Post") => ItemKind::Post,
+                _ => continue,
+            };
+
+            match Unreadable::read(kind, value) {
+                Ok(ContentView::Comment(view)) => page.comments.push(view.into()),
+                Ok(ContentView::Post(view)) => page.posts.push(view.into()),
+                Ok(ContentView::Other) => {}
+                Err(unreadable) => page.unreadable.push(unreadable),
+            }
+        }
+
+        page
+    }
+}
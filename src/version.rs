@@ -0,0 +1,116 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use crate::api::{Api, ApiError};
+
+/// The error returned when the server runs a Lemmy version this tool can't talk to.
+#[derive(Debug)]
+pub(crate) struct UnsupportedVersion(pub(crate) String);
+
+impl Display for UnsupportedVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The server runs Lemmy {}, which is not supported. Supported versions are 0.18, 0.19 and 1.x", self.0)
+    }
+}
+
+impl Error for UnsupportedVersion {}
+
+/// The flavours of the Lemmy API this tool knows how to talk to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ApiVersion {
+    /// Lemmy 0.18 expects the token as an `auth` query parameter, or as an `auth` field in the
+    /// body, and sends timestamps without a time zone.
+    V0_18,
+    /// Lemmy 0.19 expects the token in the Authorization header, and sends RFC 3339 timestamps.
+    V0_19,
+    /// Lemmy 1.0 and later serve the v4 API, with renamed endpoints, different response shapes (see
+    /// [crate::v4]) and profiles that are paged with a cursor instead of page numbers.
+    V4,
+}
+
+impl ApiVersion {
+    /// Pick the API flavour for a version reported by the server, such as `0.19.3` or `0.18.5-rc.1`.
+    pub fn from_version(version: &str) -> Result<Self, UnsupportedVersion> {
+        let mut numbers = version.trim_start_matches('v')
+            .split(|c: char| !c.is_ascii_digit())
+            .map(|number| number.parse::<u64>().ok());
+
+        match (numbers.next().flatten(), numbers.next().flatten()) {
+            (Some(0), Some(18)) => Ok(ApiVersion::V0_18),
+            (Some(0), Some(19..)) => Ok(ApiVersion::V0_19),
+            (Some(1..), _) => Ok(ApiVersion::V4),
+            _ => Err(UnsupportedVersion(version.to_string())),
+        }
+    }
+
+    /// Whether the token is sent in the Authorization header, rather than in the query or the body.
+    pub fn uses_auth_header(&self) -> bool {
+        matches!(self, ApiVersion::V0_19 | ApiVersion::V4)
+    }
+
+    /// The path all API endpoints live under.
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            ApiVersion::V0_18 | ApiVersion::V0_19 => "api/v3",
+            ApiVersion::V4 => "api/v4",
+        }
+    }
+
+    /// Whether the pages of a profile can be fetched by number, so that a crawl can skip pages.
+    pub fn has_numbered_pages(&self) -> bool {
+        !matches!(self, ApiVersion::V4)
+    }
+}
+
+/// The part of the response to a site API call that holds the version. Simplified.
+#[derive(Deserialize)]
+struct SiteVersion {
+    version: String,
+}
+
+/// Ask the server which version of Lemmy it runs, and remember the matching API flavour.
+///
+/// This call is not authenticated, as the way to send the token depends on the answer. It always
+/// goes to the v3 API, which newer versions still serve for this purpose.
+pub(crate) async fn detect(api: &Api) -> Result<ApiVersion> {
+    let response = api.client.get(api.version_url())
+        .send()
+        .await
+        .context("Failed to reach the server")?;
+    if !response.status().is_success() {
+        let error = ApiError::from_response(response).await;
+        return Err(error).context("Failed to ask the server for its version");
+    }
+
+    let site: SiteVersion = response.json().await
+        .context("The server did not report its version, is it running Lemmy?")?;
+    let version = ApiVersion::from_version(&site.version)?;
+
+    println!("The server runs Lemmy {}", site.version);
+    api.set_version(version);
+
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ApiVersion;
+
+    #[test]
+    fn maps_lemmy_versions() {
+        assert_eq!(ApiVersion::from_version("0.18.5").unwrap(), ApiVersion::V0_18);
+        assert_eq!(ApiVersion::from_version("v0.18.0").unwrap(), ApiVersion::V0_18);
+        assert_eq!(ApiVersion::from_version("0.19.3").unwrap(), ApiVersion::V0_19);
+        assert_eq!(ApiVersion::from_version("0.19.0-rc.1").unwrap(), ApiVersion::V0_19);
+        assert_eq!(ApiVersion::from_version("0.20.0").unwrap(), ApiVersion::V0_19);
+        assert_eq!(ApiVersion::from_version("1.0.0-alpha.4").unwrap(), ApiVersion::V4);
+    }
+
+    #[test]
+    fn rejects_unsupported_versions() {
+        for version in ["0.17.4", "0", "", "unknown"] {
+            assert!(ApiVersion::from_version(version).is_err(), "{version} should not be supported");
+        }
+    }
+}